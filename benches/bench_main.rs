@@ -0,0 +1,95 @@
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main, Throughput};
+
+use danton::{add_document, Document, flatten, flush_to_disk, index_documents, load_from_disk, search, SearchConfig,
+             search_flat_with_config, search_with_config, Segment};
+
+const VOCABULARY_SIZE: u64 = 5_000;
+const WORDS_PER_DOC: usize = 12;
+
+/// Reproducible corpus: words are drawn from a fixed vocabulary with a xorshift
+/// generator seeded by the document id, skewed towards low word numbers.
+fn synthetic_documents(count: usize) -> Vec<Document> {
+    (0..count as i64)
+        .map(|id| {
+            let mut state = (id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15).wrapping_add(1);
+            let words: Vec<String> = (0..WORDS_PER_DOC)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let word = (state % VOCABULARY_SIZE) * (state % VOCABULARY_SIZE) / VOCABULARY_SIZE;
+                    format!("w{}", word)
+                })
+                .collect();
+            Document::new(id, &words.join(" "))
+        })
+        .collect()
+}
+
+fn bench_index_documents(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index_documents");
+    group.sample_size(10);
+    for count in [1_000, 10_000, 100_000] {
+        let documents = synthetic_documents(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &documents, |b, documents| {
+            b.iter(|| index_documents(documents.clone()).expect(""))
+        });
+    }
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion) {
+    let segment = index_documents(synthetic_documents(100_000)).expect("");
+    let mut group = c.benchmark_group("search");
+    for tokens in [1, 5, 10] {
+        let query: Vec<String> = (0..tokens).map(|i| format!("w{}", i)).collect();
+        let query = query.join(" ");
+        group.bench_with_input(BenchmarkId::from_parameter(tokens), &query, |b, query| {
+            b.iter(|| search(&segment, query.clone(), 10).expect(""))
+        });
+    }
+    group.finish();
+}
+
+/// Same queries against the segment and its flattened copy; the query spreads
+/// over many distinct terms so the posting lists do not share cache lines.
+fn bench_flat_search(c: &mut Criterion) {
+    let segment = index_documents(synthetic_documents(100_000)).expect("");
+    let query: Vec<String> = (0..50).map(|i| format!("w{}", i * 97)).collect();
+    let query = query.join(" ");
+    let config = SearchConfig { max_query_terms: 50, ..SearchConfig::default() };
+    let mut group = c.benchmark_group("flat_search");
+    group.bench_function("segment", |b| {
+        b.iter(|| search_with_config(&segment, query.clone(), 10, &config).expect(""))
+    });
+    let flat = flatten(segment);
+    group.bench_function("flat", |b| b.iter(|| search_flat_with_config(&flat, query.clone(), 10, &config).expect("")));
+    group.finish();
+}
+
+/// Replaces one document in a segment whose frequent terms have long posting lists.
+fn bench_update_document(c: &mut Criterion) {
+    let segment = index_documents(synthetic_documents(10_000)).expect("");
+    let replacement = Document::new(5_000, "w0 w1 w2 w3 updated");
+    c.bench_function("update_document", |b| {
+        b.iter_batched_ref(|| segment.clone(),
+                           |segment| add_document(segment, replacement.clone()).expect(""),
+                           BatchSize::LargeInput)
+    });
+}
+
+fn bench_disk(c: &mut Criterion) {
+    let segment: Segment = index_documents(synthetic_documents(10_000)).expect("");
+    let path = std::env::temp_dir().join(format!("danton_bench_{}", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let mut group = c.benchmark_group("disk");
+    group.sample_size(10);
+    group.bench_function("flush_to_disk", |b| b.iter(|| flush_to_disk(&segment, &path).expect("")));
+    group.bench_function("load_from_disk", |b| b.iter(|| load_from_disk(&path).expect("")));
+    group.finish();
+    let _ = std::fs::remove_dir_all(&path);
+}
+
+criterion_group!(benches, bench_index_documents, bench_search, bench_flat_search, bench_update_document, bench_disk);
+criterion_main!(benches);
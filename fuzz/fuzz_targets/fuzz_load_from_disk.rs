@@ -0,0 +1,41 @@
+#![no_main]
+
+use std::fs;
+use std::path::PathBuf;
+
+use libfuzzer_sys::fuzz_target;
+
+use danton::load_from_disk;
+
+// Input layout: u32 LE length of the terms dict file, its bytes, u32 LE length
+// of the posting lists file, its bytes, and the docs file as the remainder.
+// Lengths are clamped to what is left, so every input maps to three files.
+fn split(data: &[u8]) -> (&[u8], &[u8], &[u8]) {
+    fn take(data: &[u8]) -> (&[u8], &[u8]) {
+        if data.len() < 4 {
+            return (&[], data);
+        }
+        let len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+        let rest = &data[4..];
+        rest.split_at(len.min(rest.len()))
+    }
+    let (terms, rest) = take(data);
+    let (postings, docs) = take(rest);
+    (terms, postings, docs)
+}
+
+fn index_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("danton_fuzz_load_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// load_from_disk must turn malformed files into an Err; any panic is a crash.
+fuzz_target!(|data: &[u8]| {
+    let (terms, postings, docs) = split(data);
+    let dir = index_dir();
+    fs::write(dir.join("terms_dict.dat"), terms).unwrap();
+    fs::write(dir.join("posting_lists.dat"), postings).unwrap();
+    fs::write(dir.join("docs.dat"), docs).unwrap();
+    let _ = load_from_disk(dir.to_str().unwrap());
+});
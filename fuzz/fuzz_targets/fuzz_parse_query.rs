@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// parse_query must only ever return Ok or Err; any panic is reported as a crash.
+fuzz_target!(|data: &[u8]| {
+    let query = String::from_utf8_lossy(data);
+    let _ = danton::parse_query(&query);
+});
@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+use log::debug;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::{decode_segment, encode_segment, POSTING_INDEX_FILE_NAME, POSTING_LISTS_FILE_NAME, SearchError, Segment,
+            STORED_DOCS_FILE_NAME, TERM_DICT_FILE_NAME};
+
+/// Async `flush_to_disk`: writes the same files, all four concurrently.
+pub async fn flush_to_disk_async(segment: &Segment, path: &str) -> Result<(), SearchError> {
+    let started = Instant::now();
+    let (mut terms, mut postings, mut index, mut docs) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    encode_segment(segment, &mut terms, &mut postings, &mut index, &mut docs)?;
+
+    fs::create_dir_all(path).await?;
+    tokio::try_join!(
+        write_file(format!("{}/{}", path, TERM_DICT_FILE_NAME), &terms),
+        write_file(format!("{}/{}", path, POSTING_LISTS_FILE_NAME), &postings),
+        write_file(format!("{}/{}", path, POSTING_INDEX_FILE_NAME), &index),
+        write_file(format!("{}/{}", path, STORED_DOCS_FILE_NAME), &docs),
+    )?;
+    debug!("flushed segment to {}: terms {} bytes, postings {} bytes, docs {} bytes in {:?}",
+           path, terms.len(), postings.len(), docs.len(), started.elapsed());
+    Ok(())
+}
+
+async fn write_file(path: String, bytes: &[u8]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path).await?);
+    writer.write_all(bytes).await?;
+    writer.flush().await
+}
+
+/// Async `load_from_disk`. The files are read concurrently, then decoded.
+pub async fn load_from_disk_async(path: &str) -> Result<Segment, SearchError> {
+    let started = Instant::now();
+    let (terms, postings, docs) = tokio::try_join!(
+        fs::read(format!("{}/{}", path, TERM_DICT_FILE_NAME)),
+        fs::read(format!("{}/{}", path, POSTING_LISTS_FILE_NAME)),
+        fs::read(format!("{}/{}", path, STORED_DOCS_FILE_NAME)),
+    )?;
+    let mut postings = postings.as_slice();
+    crate::check_version(&mut postings)?;
+    let segment = decode_segment(&mut terms.as_slice(), postings, &mut docs.as_slice())?;
+    debug!("loaded segment from {}: terms {} bytes, postings {} bytes, docs {} bytes in {:?}",
+           path, terms.len(), postings.len() + 4, docs.len(), started.elapsed());
+    Ok(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Document, flush_to_disk_async, index_documents, load_from_disk, load_from_disk_async, search};
+
+    #[tokio::test]
+    async fn flush_and_load_async_success() {
+        let dir = tempfile::tempdir().expect("");
+        let path = dir.path().to_str().expect("");
+        let segment = index_documents(vec![
+            Document::new(1, "rust search engine"),
+            Document::new(2, "rust compiler"),
+            Document::new(3, "search engine in go"),
+        ]).expect("");
+        flush_to_disk_async(&segment, path).await.expect("");
+
+        let loaded = load_from_disk_async(path).await.expect("");
+        let reloaded = load_from_disk(path).expect("");
+        assert_eq!(loaded.total_docs, 3);
+        for query in ["rust", "search engine", "missing"] {
+            let expected: Vec<i64> = search(&segment, query.to_string(), 10).expect("").iter().map(|d| d.id).collect();
+            for segment in [&loaded, &reloaded] {
+                let found: Vec<i64> = search(segment, query.to_string(), 10).expect("").iter().map(|d| d.id).collect();
+                assert_eq!(found, expected, "query {:?}", query);
+            }
+        }
+        assert_eq!(loaded.docs[&2].text, "rust compiler");
+        crate::assert_sorted(&loaded);
+    }
+}
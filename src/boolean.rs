@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{DEFAULT_MAX_QUERY_TERMS, doc_term_score, ensure_built, F32, PhraseQuery, Query, score_tf_idf, SearchError,
+            Segment, TopKDoc};
+
+/// Query over single terms. A document matches if it contains every `must`
+/// term and none of the `must_not` terms or `must_not_phrases`; without `must`
+/// terms it also needs at least one `should` term. Matching `should` terms add
+/// to the score.
+#[derive(Clone, Debug, Default)]
+pub struct BooleanQuery {
+    pub must: Vec<String>,
+    pub should: Vec<String>,
+    pub must_not: Vec<String>,
+    /// Term sequences that exclude a document where they appear adjacent and in
+    /// order, checked like `PhraseQuery` by re-tokenizing the candidates.
+    pub must_not_phrases: Vec<Vec<String>>,
+    /// Fields every term is looked up in, as "field:term". When empty, terms are
+    /// looked up in the document text.
+    pub fields: Vec<String>,
+    /// Multiplier applied to the score of matches in a field; 1.0 if absent.
+    pub field_boosts: HashMap<String, f32>,
+}
+
+impl BooleanQuery {
+    pub fn new() -> Self {
+        BooleanQuery::default()
+    }
+
+    pub fn field_boost(&self, field: &str) -> f32 {
+        self.field_boosts.get(field).copied().unwrap_or(1.0)
+    }
+
+    /// Boosted tf-idf score of `term` in every document containing it, summed
+    /// over the query fields.
+    fn term_scores(&self, segment: &Segment, term: &str) -> HashMap<i64, f32> {
+        let lookups: Vec<(String, f32)> = if self.fields.is_empty() {
+            vec![(term.to_string(), 1.0)]
+        } else {
+            self.fields.iter().map(|field| (format!("{}:{}", field, term), self.field_boost(field))).collect()
+        };
+        let mut scores = HashMap::new();
+        for (key, boost) in lookups {
+            if let Some(posting) = segment.dict.get(&key) {
+                let doc_freq = posting.len() as i32;
+                for node in posting.nodes() {
+                    let score = boost * score_tf_idf(node.freq, doc_freq, segment.total_docs as i32);
+                    *scores.entry(node.doc_id).or_insert(0.0) += score;
+                }
+            }
+        }
+        scores
+    }
+
+    /// Whether one of `must_not_phrases` occurs in the document, in any of the
+    /// query fields.
+    fn has_excluded_phrase(&self, segment: &Segment, doc_id: i64) -> bool {
+        self.must_not_phrases.iter().filter(|phrase| !phrase.is_empty()).any(|phrase| {
+            if self.fields.is_empty() {
+                return PhraseQuery { terms: phrase.clone(), slop: 0 }.matches(segment, doc_id);
+            }
+            self.fields.iter().any(|field| {
+                let terms = phrase.iter().map(|term| format!("{}:{}", field, term)).collect();
+                PhraseQuery { terms, slop: 0 }.matches(segment, doc_id)
+            })
+        })
+    }
+}
+
+impl BooleanQuery {
+    /// Boosted score of `term` in one document summed over the query fields,
+    /// `None` if no field of the document holds the term.
+    fn doc_score(&self, segment: &Segment, term: &str, doc_id: i64) -> Option<f32> {
+        if self.fields.is_empty() {
+            return doc_term_score(segment, term, doc_id);
+        }
+        self.fields.iter()
+            .filter_map(|field| {
+                doc_term_score(segment, &format!("{}:{}", field, term), doc_id).map(|score| self.field_boost(field) * score)
+            })
+            .reduce(|a, b| a + b)
+    }
+}
+
+/// Same matching and scoring as `search_boolean`, one document at a time.
+impl Query for BooleanQuery {
+    fn matches(&self, segment: &Segment, doc_id: i64) -> bool {
+        let has = |term: &String| self.doc_score(segment, term, doc_id).is_some();
+        let required = if self.must.is_empty() { self.should.iter().any(has) } else { self.must.iter().all(has) };
+        required && !self.must_not.iter().any(has) && !self.has_excluded_phrase(segment, doc_id)
+    }
+
+    fn score(&self, segment: &Segment, doc_id: i64) -> f32 {
+        self.must.iter().chain(&self.should).filter_map(|term| self.doc_score(segment, term, doc_id)).sum()
+    }
+}
+
+/// Runs a `BooleanQuery`, best matches first; equal scores are ordered by doc_id.
+/// Fails with `TooManyClauses` if the query has more than
+/// `DEFAULT_MAX_QUERY_TERMS` terms and phrases in total.
+pub fn search_boolean(segment: &Segment, query: &BooleanQuery, size: i32) -> Result<Vec<TopKDoc>, SearchError> {
+    ensure_built(segment)?;
+    if query.must.is_empty() && query.should.is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+    let clauses = query.must.len() + query.should.len() + query.must_not.len() + query.must_not_phrases.len();
+    if clauses > DEFAULT_MAX_QUERY_TERMS {
+        return Err(SearchError::TooManyClauses(clauses));
+    }
+
+    let mut scores: HashMap<i64, f32> = HashMap::new();
+    let mut must_hits: HashMap<i64, usize> = HashMap::new();
+    for term in &query.must {
+        for (doc_id, score) in query.term_scores(segment, term) {
+            *scores.entry(doc_id).or_insert(0.0) += score;
+            *must_hits.entry(doc_id).or_insert(0) += 1;
+        }
+    }
+    let mut should_hits = HashSet::new();
+    for term in &query.should {
+        for (doc_id, score) in query.term_scores(segment, term) {
+            *scores.entry(doc_id).or_insert(0.0) += score;
+            should_hits.insert(doc_id);
+        }
+    }
+    let excluded: HashSet<i64> = query.must_not.iter()
+        .flat_map(|term| query.term_scores(segment, term).into_keys())
+        .collect();
+
+    let mut result: Vec<TopKDoc> = scores.into_iter()
+        .filter(|(doc_id, _)| if query.must.is_empty() {
+            should_hits.contains(doc_id)
+        } else {
+            must_hits.get(doc_id) == Some(&query.must.len())
+        })
+        .filter(|(doc_id, _)| !excluded.contains(doc_id))
+        .filter(|(doc_id, _)| !query.has_excluded_phrase(segment, *doc_id))
+        .map(|(id, score)| TopKDoc { id, score: F32(score) })
+        .collect();
+    result.sort_by(|a, b| a.score.cmp(&b.score).then(a.id.cmp(&b.id)));
+    result.truncate(size.max(0) as usize);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BooleanQuery, Document, index_documents, Query, search_boolean, SearchError};
+
+    fn document(id: i64, title: &str, body: &str) -> Document {
+        let mut document = Document::new(id, "");
+        document.fields.insert("title".to_string(), vec![title.to_string()]);
+        document.fields.insert("body".to_string(), vec![body.to_string()]);
+        document
+    }
+
+    #[test]
+    fn search_boolean_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "rust search engine"),
+            Document::new(2, "rust compiler"),
+            Document::new(3, "search engine in go"),
+            Document::new(4, "rust search"),
+        ]).expect("");
+
+        let query = BooleanQuery {
+            must: vec!["rust".to_string()],
+            should: vec!["engine".to_string()],
+            must_not: vec!["compiler".to_string()],
+            ..BooleanQuery::new()
+        };
+        let ids: Vec<i64> = search_boolean(&segment, &query, 10).expect("").iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![1, 4]);
+
+        let query = BooleanQuery { should: vec!["engine".to_string(), "compiler".to_string()], ..BooleanQuery::new() };
+        let mut ids: Vec<i64> = search_boolean(&segment, &query, 10).expect("").iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        assert!(matches!(search_boolean(&segment, &BooleanQuery::new(), 10), Err(SearchError::EmptyQuery)));
+        let query = BooleanQuery {
+            must: vec!["rust".to_string()],
+            must_not: (0..16).map(|i| format!("term{}", i)).collect(),
+            ..BooleanQuery::new()
+        };
+        assert!(matches!(search_boolean(&segment, &query, 10), Err(SearchError::TooManyClauses(17))));
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_boolean_must_not_phrases_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "the quick brown fox"),
+            Document::new(2, "brown and quick fox"),
+            Document::new(3, "quick red brown fox"),
+            document(4, "quick brown", "fox"),
+        ]).expect("");
+        let phrase = vec!["quick".to_string(), "brown".to_string()];
+        let query = BooleanQuery {
+            must: vec!["fox".to_string()],
+            must_not_phrases: vec![phrase.clone()],
+            ..BooleanQuery::new()
+        };
+        let mut ids: Vec<i64> = search_boolean(&segment, &query, 10).expect("").iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![2, 3]);
+        assert!(query.matches(&segment, 2) && !query.matches(&segment, 1));
+
+        let query = BooleanQuery {
+            must: vec!["fox".to_string()],
+            must_not_phrases: vec![phrase],
+            fields: vec!["title".to_string(), "body".to_string()],
+            ..BooleanQuery::new()
+        };
+        assert!(search_boolean(&segment, &query, 10).expect("").is_empty());
+        let query = BooleanQuery { must_not_phrases: Vec::new(), ..query };
+        assert_eq!(search_boolean(&segment, &query, 10).expect("").iter().map(|d| d.id).collect::<Vec<i64>>(), vec![4]);
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_boolean_must_not_phrases_multi_value_success() {
+        let mut split = Document::new(1, "fox");
+        split.fields.insert("tag".to_string(), vec!["quick fox".to_string(), "the brown".to_string()]);
+        let mut joined = Document::new(2, "fox");
+        joined.fields.insert("tag".to_string(), vec!["red".to_string(), "quick brown".to_string()]);
+        let segment = index_documents(vec![split, joined]).expect("");
+        let query = BooleanQuery {
+            must: vec!["fox".to_string()],
+            must_not_phrases: vec![vec!["quick".to_string(), "brown".to_string()]],
+            fields: vec!["tag".to_string()],
+            ..BooleanQuery::new()
+        };
+        assert!(query.matches(&segment, 1) && !query.matches(&segment, 2));
+        assert_eq!(search_boolean(&segment, &query, 10).expect("").iter().map(|d| d.id).collect::<Vec<i64>>(), vec![1]);
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_boolean_field_boosts_success() {
+        let segment = index_documents(vec![
+            document(1, "a guide", "learning rust"),
+            document(2, "rust", "a guide"),
+            document(3, "cooking", "recipes"),
+        ]).expect("");
+        let mut query = BooleanQuery {
+            must: vec!["rust".to_string()],
+            fields: vec!["title".to_string(), "body".to_string()],
+            ..BooleanQuery::new()
+        };
+        let hits = search_boolean(&segment, &query, 10).expect("");
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].score.0, hits[1].score.0);
+
+        query.field_boosts.insert("title".to_string(), 3.0);
+        let hits = search_boolean(&segment, &query, 10).expect("");
+        assert_eq!(hits.iter().map(|d| d.id).collect::<Vec<i64>>(), vec![2, 1]);
+        assert_eq!(hits[0].score.0, 3.0 * hits[1].score.0);
+        crate::assert_sorted(&segment);
+    }
+}
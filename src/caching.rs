@@ -0,0 +1,86 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+
+use crate::{add_document, Document, search, SearchError, Segment, TopKDoc};
+
+/// Memoises the results of recently repeated queries against one segment.
+/// Adding a document through the searcher clears the cache.
+pub struct CachingSearcher {
+    inner: Arc<Segment>,
+    cache: Mutex<LruCache<(String, i32), Vec<TopKDoc>>>,
+    hits: AtomicU64,
+}
+
+impl CachingSearcher {
+    /// Keeps the results of up to `capacity` distinct queries (at least one).
+    pub fn new(segment: Arc<Segment>, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        CachingSearcher { inner: segment, cache: Mutex::new(LruCache::new(capacity)), hits: AtomicU64::new(0) }
+    }
+
+    /// Like `HotReloadableIndex::search`, failed searches return no hits; those
+    /// are cached too.
+    pub fn search(&self, query: &str, size: i32) -> Vec<TopKDoc> {
+        let key = (query.to_string(), size);
+        if let Some(results) = self.cache.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_cache_lookup(true);
+            return results.clone();
+        }
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_cache_lookup(false);
+        let results = search(&self.inner, query.to_string(), size).unwrap_or_default();
+        self.cache.lock().unwrap().put(key, results.clone());
+        results
+    }
+
+    /// Adds or replaces a document and drops every cached result. The segment
+    /// is copied first if other owners still share it.
+    pub fn add_document(&mut self, document: Document) -> Result<(), SearchError> {
+        add_document(Arc::make_mut(&mut self.inner), document)?;
+        self.cache.get_mut().unwrap().clear();
+        Ok(())
+    }
+
+    pub fn segment(&self) -> &Arc<Segment> {
+        &self.inner
+    }
+
+    /// Searches answered from the cache so far.
+    pub fn cache_hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{CachingSearcher, Document, index_documents};
+
+    #[test]
+    fn caching_searcher_success() {
+        let segment = index_documents(vec![Document::new(1, "rust search"), Document::new(2, "go")]).expect("");
+        let mut searcher = CachingSearcher::new(Arc::new(segment), 8);
+
+        let first = searcher.search("rust", 10);
+        assert_eq!(searcher.cache_hits(), 0);
+        let second = searcher.search("rust", 10);
+        assert_eq!(searcher.cache_hits(), 1);
+        assert_eq!(first.iter().map(|d| d.id).collect::<Vec<i64>>(), vec![1]);
+        assert!(first.iter().zip(&second).all(|(a, b)| a.same_hit(b)));
+        searcher.search("rust", 5);
+        assert_eq!(searcher.cache_hits(), 1);
+
+        searcher.add_document(Document::new(3, "rust compiler")).expect("");
+        let mut ids: Vec<i64> = searcher.search("rust", 10).iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(searcher.cache_hits(), 1);
+        crate::assert_sorted(searcher.segment());
+    }
+}
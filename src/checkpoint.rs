@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::{Document, flush_to_disk, index_into, IndexConfig, load_from_disk, SearchError, Segment};
+
+/// Where and how often `index_documents_checkpointed` saves its progress.
+#[derive(Clone, Debug)]
+pub struct CheckpointWriter {
+    /// Documents indexed between two flushes; 0 is treated as 1.
+    pub interval: usize,
+    pub path: String,
+}
+
+impl CheckpointWriter {
+    pub fn new(interval: usize, path: &str) -> Self {
+        CheckpointWriter { interval, path: path.to_string() }
+    }
+}
+
+/// Indexes `documents` like `index_documents`, flushing the partial segment to
+/// `checkpoint.path` after every `checkpoint.interval` documents and once at
+/// the end. After a crash, `resume_from_checkpoint` picks up from the last flush.
+///
+/// Every flush rewrites the whole partial segment, so the total bytes written
+/// grow quadratically with the number of checkpoints; pick the interval with
+/// that in mind.
+pub fn index_documents_checkpointed(documents: Vec<Document>,
+                                    checkpoint: &CheckpointWriter) -> Result<Segment, SearchError> {
+    let mut ids = HashSet::with_capacity(documents.len());
+    if let Some(document) = documents.iter().find(|document| !ids.insert(document.id)) {
+        return Err(SearchError::DuplicateDocumentId(document.id));
+    }
+
+    let config = IndexConfig::default();
+    let mut segment = Segment::new();
+    let mut documents = documents.into_iter();
+    loop {
+        let chunk: Vec<Document> = documents.by_ref().take(checkpoint.interval.max(1)).collect();
+        if chunk.is_empty() {
+            return Ok(segment);
+        }
+        segment = index_into(segment, chunk, &config)?;
+        flush_checkpoint(&segment, &checkpoint.path)?;
+    }
+}
+
+/// Flushes into `<path>.tmp` and only then swaps it in for `path`, so a crash
+/// mid-flush leaves the previous checkpoint untouched. Directories cannot be
+/// renamed over each other, so the old one is moved to `<path>.old` first; a
+/// crash between the two renames leaves it there for `resume_from_checkpoint`.
+fn flush_checkpoint(segment: &Segment, path: &str) -> Result<(), SearchError> {
+    let tmp = format!("{}.tmp", path);
+    let old = format!("{}.old", path);
+    if Path::new(&tmp).exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    flush_to_disk(segment, &tmp)?;
+    if Path::new(path).exists() {
+        if Path::new(&old).exists() {
+            fs::remove_dir_all(&old)?;
+        }
+        fs::rename(path, &old)?;
+    }
+    fs::rename(&tmp, path)?;
+    if Path::new(&old).exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+/// Loads the last checkpoint in `path` and indexes the documents it does not
+/// hold yet; documents already in the checkpoint are skipped, so the whole
+/// original input may be passed again. Nothing more is flushed.
+pub fn resume_from_checkpoint(path: &str, remaining: Vec<Document>) -> Result<Segment, SearchError> {
+    let old = format!("{}.old", path);
+    let segment = if !Path::new(path).exists() && Path::new(&old).exists() {
+        load_from_disk(&old)?
+    } else {
+        load_from_disk(path)?
+    };
+    let remaining: Vec<Document> = remaining.into_iter()
+        .filter(|document| !segment.docs.contains_key(&document.id))
+        .collect();
+    index_into(segment, remaining, &IndexConfig::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{CheckpointWriter, Document, flush_to_disk, index_documents, index_documents_checkpointed,
+                load_from_disk, POSTING_LISTS_FILE_NAME, resume_from_checkpoint, search};
+
+    fn documents(range: std::ops::Range<i64>) -> Vec<Document> {
+        range.map(|id| Document::new(id, &format!("doc{} group{} shared", id, id % 3))).collect()
+    }
+
+    #[test]
+    fn resume_from_checkpoint_success() {
+        let dir = tempfile::tempdir().expect("");
+        let path = dir.path().to_str().expect("");
+
+        // the run is cut short after 25 of 40 documents
+        let checkpoint = CheckpointWriter::new(10, path);
+        let partial = index_documents_checkpointed(documents(0..25), &checkpoint).expect("");
+        assert_eq!(partial.total_docs, 25);
+        assert_eq!(load_from_disk(path).expect("").total_docs, 25);
+
+        let resumed = resume_from_checkpoint(path, documents(0..40)).expect("");
+        let full = index_documents(documents(0..40)).expect("");
+        assert_eq!(resumed.total_docs, 40);
+        for query in ["shared", "group1", "doc33", "doc3 group0"] {
+            let ids = |segment| -> Vec<(i64, f32)> {
+                search(segment, query.to_string(), 50).expect("").iter().map(|d| (d.id, d.score.0)).collect()
+            };
+            assert_eq!(ids(&resumed), ids(&full), "query {:?}", query);
+        }
+        crate::assert_sorted(&resumed);
+    }
+
+    #[test]
+    fn interrupted_checkpoint_flush_success() {
+        let dir = tempfile::tempdir().expect("");
+        let path = dir.path().join("checkpoint");
+        let path = path.to_str().expect("");
+        let checkpoint = CheckpointWriter::new(10, path);
+        index_documents_checkpointed(documents(0..20), &checkpoint).expect("");
+        assert!(!fs::exists(format!("{}.tmp", path)).expect(""));
+
+        // killed while writing the next checkpoint: its files are cut short
+        let tmp = format!("{}.tmp", path);
+        flush_to_disk(&index_documents(documents(0..30)).expect(""), &tmp).expect("");
+        let postings = format!("{}/{}", tmp, POSTING_LISTS_FILE_NAME);
+        let bytes = fs::read(&postings).expect("");
+        fs::write(&postings, &bytes[..bytes.len() / 2]).expect("");
+        assert_eq!(load_from_disk(path).expect("").total_docs, 20);
+        assert_eq!(resume_from_checkpoint(path, documents(0..40)).expect("").total_docs, 40);
+
+        // killed between moving the old checkpoint aside and renaming the new one
+        fs::rename(path, format!("{}.old", path)).expect("");
+        let resumed = resume_from_checkpoint(path, documents(0..40)).expect("");
+        assert_eq!(resumed.total_docs, 40);
+
+        // the next run replaces the leftovers
+        index_documents_checkpointed(documents(0..25), &checkpoint).expect("");
+        assert_eq!(load_from_disk(path).expect("").total_docs, 25);
+        assert!(!fs::exists(&tmp).expect(""));
+        assert!(!fs::exists(format!("{}.old", path)).expect(""));
+        crate::assert_sorted(&resumed);
+    }
+}
@@ -0,0 +1,120 @@
+//! Structures that can be written from several threads without a lock.
+
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::{PostingList, PostingNode};
+
+struct Node {
+    posting: PostingNode,
+    next: *mut Node,
+}
+
+/// Append-only posting list shared between indexing threads. Nodes are pushed
+/// onto the head of a linked list with a compare-and-swap, so producers never
+/// block each other; `to_posting_list` turns it back into a regular list.
+pub struct LockFreePostingList {
+    head: AtomicPtr<Node>,
+    len: AtomicUsize,
+}
+
+impl LockFreePostingList {
+    pub fn new() -> Self {
+        LockFreePostingList { head: AtomicPtr::new(ptr::null_mut()), len: AtomicUsize::new(0) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Nodes in the order they were appended.
+    pub fn nodes(&self) -> Vec<PostingNode> {
+        let mut nodes = Vec::with_capacity(self.len());
+        let mut current = self.head.load(Ordering::Acquire);
+        while !current.is_null() {
+            // Safety: published nodes are never mutated or freed until drop.
+            let node = unsafe { &*current };
+            nodes.push(node.posting.clone());
+            current = node.next;
+        }
+        nodes.reverse();
+        nodes
+    }
+
+    /// Collects the nodes into a `PostingList`, summing the frequencies of nodes
+    /// appended for the same doc_id.
+    pub fn to_posting_list(&self) -> PostingList {
+        let mut posting = PostingList::new();
+        for node in self.nodes() {
+            posting.add(node.doc_id, node.freq);
+        }
+        posting
+    }
+}
+
+impl Default for LockFreePostingList {
+    fn default() -> Self {
+        LockFreePostingList::new()
+    }
+}
+
+impl Drop for LockFreePostingList {
+    fn drop(&mut self) {
+        let mut current = *self.head.get_mut();
+        while !current.is_null() {
+            // Safety: `&mut self` means no other thread can still reach the nodes.
+            let node = unsafe { Box::from_raw(current) };
+            current = node.next;
+        }
+    }
+}
+
+/// Appends a node; safe to call from any number of threads at once.
+pub fn append(list: &LockFreePostingList, node: PostingNode) {
+    let new = Box::into_raw(Box::new(Node { posting: node, next: ptr::null_mut() }));
+    let mut head = list.head.load(Ordering::Relaxed);
+    loop {
+        // Safety: `new` is not published yet, so this thread owns it.
+        unsafe { (*new).next = head };
+        match list.head.compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(current) => head = current,
+        }
+    }
+    list.len.fetch_add(1, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::concurrent::{append, LockFreePostingList};
+    use crate::PostingNode;
+
+    #[test]
+    fn concurrent_append_success() {
+        let list = Arc::new(LockFreePostingList::new());
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let list = Arc::clone(&list);
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        append(&list, PostingNode { doc_id: t * 1000 + i, freq: 1 });
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().expect(""));
+
+        assert_eq!(list.len(), 8000);
+        let mut ids: Vec<i64> = list.nodes().iter().map(|n| n.doc_id).collect();
+        ids.sort();
+        assert_eq!(ids, (0..8000).collect::<Vec<i64>>());
+        assert_eq!(list.to_posting_list().len(), 8000);
+    }
+}
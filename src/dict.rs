@@ -0,0 +1,285 @@
+//! Term dictionary backends. The default one is built on `gtrie`; the `btree`
+//! feature swaps it for a plain `BTreeMap`. Both expose the same API.
+
+use std::collections::BTreeSet;
+
+pub use backend::TermDict;
+
+#[cfg(not(feature = "btree"))]
+mod backend {
+    use std::collections::BTreeSet;
+
+    use gtrie::Trie;
+
+    use crate::PostingList;
+
+    /// Term dictionary. The trie maps each term to the slot of its posting
+    /// list in `postings`, so a posting can be updated in place; since the
+    /// trie cannot enumerate its keys, the sorted set of terms is kept next to it.
+    pub struct TermDict {
+        trie: Trie<char, usize>,
+        postings: Vec<PostingList>,
+        terms: BTreeSet<String>,
+        fields: BTreeSet<String>,
+    }
+
+    impl TermDict {
+        pub fn new() -> Self {
+            TermDict { trie: Trie::new(), postings: Vec::new(), terms: BTreeSet::new(), fields: BTreeSet::new() }
+        }
+
+        fn slot(&self, term: &str) -> Option<usize> {
+            // gtrie panics when asked for a key that is only a prefix of another one
+            if !self.terms.contains(term) {
+                return None;
+            }
+            self.trie.get_value(term.chars())
+        }
+
+        pub fn get(&self, term: &str) -> Option<PostingList> {
+            self.slot(term).map(|slot| self.postings[slot].clone())
+        }
+
+        pub fn get_mut(&mut self, term: &str) -> Option<&mut PostingList> {
+            self.slot(term).map(|slot| &mut self.postings[slot])
+        }
+
+        /// Posting list of `term`, inserting an empty one if the term is new.
+        pub fn get_or_insert_default(&mut self, term: &str) -> &mut PostingList {
+            let slot = match self.slot(term) {
+                Some(slot) => slot,
+                None => {
+                    self.trie.insert(term.chars(), self.postings.len());
+                    self.terms.insert(term.to_string());
+                    super::add_field(&mut self.fields, term);
+                    self.postings.push(PostingList::default());
+                    self.postings.len() - 1
+                }
+            };
+            &mut self.postings[slot]
+        }
+
+        pub fn contains(&self, term: &str) -> bool {
+            self.terms.contains(term)
+        }
+
+        pub fn insert(&mut self, term: &str, posting: PostingList) {
+            *self.get_or_insert_default(term) = posting;
+        }
+
+        /// Keeps only the terms for which `f` returns true. gtrie cannot remove
+        /// keys, so the trie and the postings are rebuilt from the surviving terms.
+        pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+            let before = self.terms.len();
+            self.terms.retain(|t| f(t));
+            if self.terms.len() == before {
+                return;
+            }
+            let mut old = std::mem::take(&mut self.postings);
+            let mut trie = Trie::new();
+            for term in &self.terms {
+                let slot = self.trie.get_value(term.chars()).expect("term listed in dict");
+                trie.insert(term.chars(), self.postings.len());
+                self.postings.push(std::mem::take(&mut old[slot]));
+            }
+            self.trie = trie;
+            self.fields = super::fields_of(self.terms());
+        }
+
+        pub fn len(&self) -> usize {
+            self.terms.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.terms.is_empty()
+        }
+
+        /// Terms in lexicographic order.
+        pub fn terms(&self) -> impl Iterator<Item = &str> {
+            self.terms.iter().map(|t| t.as_str())
+        }
+
+        /// Field names of the `field:value` terms, in lexicographic order.
+        pub fn fields(&self) -> impl Iterator<Item = &str> {
+            self.fields.iter().map(|f| f.as_str())
+        }
+    }
+
+    // gtrie::Trie is not Clone, so the copy is rebuilt term by term.
+    impl Clone for TermDict {
+        fn clone(&self) -> Self {
+            let mut dict = TermDict::new();
+            for term in self.terms() {
+                dict.insert(term, self.get(term).expect("term listed in dict"));
+            }
+            dict
+        }
+    }
+}
+
+#[cfg(feature = "btree")]
+mod backend {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::PostingList;
+
+    /// Term dictionary backed by a `BTreeMap` keyed by the term itself.
+    #[derive(Clone)]
+    pub struct TermDict {
+        map: BTreeMap<String, PostingList>,
+        fields: BTreeSet<String>,
+    }
+
+    impl TermDict {
+        pub fn new() -> Self {
+            TermDict { map: BTreeMap::new(), fields: BTreeSet::new() }
+        }
+
+        pub fn get(&self, term: &str) -> Option<PostingList> {
+            self.map.get(term).cloned()
+        }
+
+        pub fn get_mut(&mut self, term: &str) -> Option<&mut PostingList> {
+            self.map.get_mut(term)
+        }
+
+        /// Posting list of `term`, inserting an empty one if the term is new.
+        pub fn get_or_insert_default(&mut self, term: &str) -> &mut PostingList {
+            // entry() would allocate the key even when the term is already there
+            if !self.map.contains_key(term) {
+                self.map.insert(term.to_string(), PostingList::default());
+                super::add_field(&mut self.fields, term);
+            }
+            self.map.get_mut(term).expect("term just inserted")
+        }
+
+        pub fn contains(&self, term: &str) -> bool {
+            self.map.contains_key(term)
+        }
+
+        pub fn insert(&mut self, term: &str, posting: PostingList) {
+            self.map.insert(term.to_string(), posting);
+            super::add_field(&mut self.fields, term);
+        }
+
+        /// Keeps only the terms for which `f` returns true.
+        pub fn retain(&mut self, mut f: impl FnMut(&str) -> bool) {
+            let before = self.map.len();
+            self.map.retain(|t, _| f(t));
+            if self.map.len() != before {
+                self.fields = super::fields_of(self.terms());
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.map.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.map.is_empty()
+        }
+
+        /// Terms in lexicographic order.
+        pub fn terms(&self) -> impl Iterator<Item = &str> {
+            self.map.keys().map(|t| t.as_str())
+        }
+
+        /// Field names of the `field:value` terms, in lexicographic order.
+        pub fn fields(&self) -> impl Iterator<Item = &str> {
+            self.fields.iter().map(|f| f.as_str())
+        }
+    }
+}
+
+fn add_field(fields: &mut BTreeSet<String>, term: &str) {
+    if let Some((field, _)) = term.split_once(':') {
+        if !fields.contains(field) {
+            fields.insert(field.to_string());
+        }
+    }
+}
+
+/// Distinct field names of `terms`.
+fn fields_of<'a>(terms: impl Iterator<Item = &'a str>) -> BTreeSet<String> {
+    let mut fields = BTreeSet::new();
+    for term in terms {
+        add_field(&mut fields, term);
+    }
+    fields
+}
+
+impl Default for TermDict {
+    fn default() -> Self {
+        TermDict::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PostingList, PostingNode, TermDict};
+
+    fn posting(doc_id: i64) -> PostingList {
+        std::iter::once(PostingNode { doc_id, freq: 1 }).collect()
+    }
+
+    #[test]
+    fn dict_insert_and_get_success() {
+        let mut dict = TermDict::new();
+        dict.insert("test", posting(1));
+        dict.insert("tea", posting(2));
+        dict.insert("test", posting(3));
+
+        assert_eq!(dict.len(), 2);
+        assert!(dict.get("test").expect("").contains(3));
+        assert!(dict.get("tea").expect("").contains(2));
+        assert!(dict.get("te").is_none());
+        assert!(dict.contains("tea"));
+        assert!(!dict.contains("te"));
+        assert!(dict.get("missing").is_none());
+        assert_eq!(dict.terms().collect::<Vec<&str>>(), vec!["tea", "test"]);
+    }
+
+    #[test]
+    fn dict_fields_success() {
+        let mut dict = TermDict::new();
+        dict.insert("title:rust", posting(1));
+        dict.get_or_insert_default("body:rust").add(2, 1);
+        dict.insert("title:go", posting(3));
+        dict.insert("rust", posting(4));
+        assert_eq!(dict.fields().collect::<Vec<&str>>(), vec!["body", "title"]);
+
+        dict.retain(|t| !t.starts_with("body:"));
+        assert_eq!(dict.fields().collect::<Vec<&str>>(), vec!["title"]);
+    }
+
+    #[test]
+    fn dict_retain_success() {
+        let mut dict = TermDict::new();
+        dict.insert("tea", posting(1));
+        dict.insert("test", posting(2));
+        dict.insert("toast", posting(3));
+        dict.retain(|t| t != "test");
+
+        assert_eq!(dict.len(), 2);
+        assert!(dict.get("test").is_none());
+        assert!(dict.get("toast").expect("").contains(3));
+        assert_eq!(dict.terms().collect::<Vec<&str>>(), vec!["tea", "toast"]);
+        dict.get_mut("toast").expect("").add(4, 2);
+        assert_eq!(dict.get("toast").expect("").get(4).expect("").freq, 2);
+    }
+
+    #[test]
+    fn dict_get_mut_success() {
+        let mut dict = TermDict::new();
+        dict.get_or_insert_default("test").add(1, 1);
+        dict.get_or_insert_default("tea").add(2, 1);
+        dict.get_or_insert_default("test").add(1, 2);
+
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get("test").expect("").get(1).expect("").freq, 3);
+        assert!(dict.get_mut("te").is_none());
+        dict.get_mut("tea").expect("").remove(2);
+        assert!(dict.get("tea").expect("").is_empty());
+        assert_eq!(dict.terms().collect::<Vec<&str>>(), vec!["tea", "test"]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Retrieval quality metrics over ranked doc ids and relevance judgements.
+
+use std::collections::{HashMap, HashSet};
+
+/// Fraction of the first `k` results that are relevant. Missing positions count
+/// as non-relevant, so the denominator is always `k`.
+pub fn precision_at_k(results: &[i64], relevant: &HashSet<i64>, k: usize) -> f32 {
+    if k == 0 {
+        return 0.0;
+    }
+    relevant_in_top_k(results, relevant, k) as f32 / k as f32
+}
+
+/// Fraction of all relevant documents found in the first `k` results.
+pub fn recall_at_k(results: &[i64], relevant: &HashSet<i64>, k: usize) -> f32 {
+    if relevant.is_empty() {
+        return 0.0;
+    }
+    relevant_in_top_k(results, relevant, k) as f32 / relevant.len() as f32
+}
+
+/// Mean of precision@i over every rank i holding a relevant document, divided by
+/// the total number of relevant documents.
+pub fn average_precision(results: &[i64], relevant: &HashSet<i64>) -> f32 {
+    if relevant.is_empty() {
+        return 0.0;
+    }
+    let mut hits = 0;
+    let mut sum = 0.0;
+    for (i, doc_id) in results.iter().enumerate() {
+        if relevant.contains(doc_id) {
+            hits += 1;
+            sum += hits as f32 / (i + 1) as f32;
+        }
+    }
+    sum / relevant.len() as f32
+}
+
+/// Normalised discounted cumulative gain over the first `k` results, with gain
+/// `2^rel - 1` and discount `log2(rank + 1)`. Documents missing from `relevance`
+/// have grade 0. Returns 0.0 when no document has a positive grade.
+pub fn ndcg_at_k(results: &[i64], relevance: &HashMap<i64, u32>, k: usize) -> f32 {
+    let grades: Vec<u32> = results.iter()
+        .take(k)
+        .map(|doc_id| relevance.get(doc_id).copied().unwrap_or(0))
+        .collect();
+    let mut ideal: Vec<u32> = relevance.values().copied().collect();
+    ideal.sort_by(|a, b| b.cmp(a));
+    ideal.truncate(k);
+
+    let ideal_dcg = dcg(&ideal);
+    if ideal_dcg == 0.0 {
+        return 0.0;
+    }
+    dcg(&grades) / ideal_dcg
+}
+
+fn dcg(grades: &[u32]) -> f32 {
+    grades.iter()
+        .enumerate()
+        .map(|(i, &grade)| (2f32.powi(grade as i32) - 1.0) / ((i + 2) as f32).log2())
+        .sum()
+}
+
+fn relevant_in_top_k(results: &[i64], relevant: &HashSet<i64>, k: usize) -> usize {
+    results.iter().take(k).filter(|doc_id| relevant.contains(doc_id)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::eval::{average_precision, ndcg_at_k, precision_at_k, recall_at_k};
+
+    #[test]
+    fn precision_and_recall_success() {
+        let results = vec![1, 2, 3, 4, 5];
+        let relevant: HashSet<i64> = vec![1, 3, 6, 7].into_iter().collect();
+        assert_eq!(precision_at_k(&results, &relevant, 1), 1.0);
+        assert_eq!(precision_at_k(&results, &relevant, 2), 0.5);
+        assert_eq!(precision_at_k(&results, &relevant, 4), 0.5);
+        assert_eq!(precision_at_k(&results, &relevant, 10), 0.2);
+        assert_eq!(recall_at_k(&results, &relevant, 2), 0.25);
+        assert_eq!(recall_at_k(&results, &relevant, 5), 0.5);
+        assert_eq!(recall_at_k(&results, &HashSet::new(), 5), 0.0);
+    }
+
+    #[test]
+    fn average_precision_success() {
+        let results = vec![1, 2, 3, 4, 5];
+        let relevant: HashSet<i64> = vec![1, 3, 6, 7].into_iter().collect();
+        // (1/1 + 2/3) / 4
+        assert_eq!(average_precision(&results, &relevant), (1.0 + 2.0 / 3.0) / 4.0);
+
+        let all: HashSet<i64> = results.iter().copied().collect();
+        assert_eq!(average_precision(&results, &all), 1.0);
+        assert_eq!(average_precision(&results, &HashSet::new()), 0.0);
+    }
+
+    #[test]
+    fn ndcg_at_k_success() {
+        let results = vec![3, 1, 2];
+        let relevance: HashMap<i64, u32> = vec![(1, 3), (2, 0), (3, 1)].into_iter().collect();
+        // dcg = 1/log2(2) + 7/log2(3), idcg = 7/log2(2) + 1/log2(3)
+        assert!((ndcg_at_k(&results, &relevance, 3) - 0.709_809_7).abs() < 1e-6);
+        assert!((ndcg_at_k(&results, &relevance, 1) - 1.0 / 7.0).abs() < 1e-6);
+        assert_eq!(ndcg_at_k(&[1, 3, 2], &relevance, 3), 1.0);
+        assert_eq!(ndcg_at_k(&results, &HashMap::new(), 3), 0.0);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::{avg_doc_len, check_query_terms, CollectionStats, Document, global_doc_freq, IndexTokenizer, Iter,
+            normalize_scores, PostingIter, PostingNode, query_terms, resolve_field_alias, score_iterators,
+            SearchConfig, SearchError, Segment, source_matches, TopKDoc};
+
+/// Read-only segment whose posting lists share one contiguous arena, so a
+/// search walks adjacent memory instead of one allocation per term.
+pub struct FlatSegment {
+    /// Posting nodes of every term, term after term, each run sorted by doc_id.
+    pub arena: Vec<PostingNode>,
+    /// Term to its `(start, end)` range in `arena`.
+    pub index: HashMap<String, (usize, usize)>,
+    pub docs: HashMap<i64, Arc<Document>>,
+    pub doc_lengths: HashMap<i64, u32>,
+    pub total_docs: usize,
+    /// Tokenizer of the segment it was flattened from, applied to queries.
+    pub tokenizer: Option<Arc<IndexTokenizer>>,
+    /// Field names of the `field:value` terms in `index`.
+    fields: BTreeSet<String>,
+    avg_doc_len: f32,
+}
+
+impl FlatSegment {
+    /// Nodes of `term`, empty when it is not indexed.
+    pub fn postings(&self, term: &str) -> &[PostingNode] {
+        match self.index.get(term) {
+            Some(&(start, end)) => &self.arena[start..end],
+            None => &[],
+        }
+    }
+}
+
+pub fn flatten(segment: Segment) -> FlatSegment {
+    let mut arena = Vec::new();
+    let mut index = HashMap::with_capacity(segment.dict.len());
+    for term in segment.dict.terms() {
+        let posting = segment.dict.get(term).expect("term listed in dict");
+        let start = arena.len();
+        arena.extend(posting.nodes());
+        index.insert(term.to_string(), (start, arena.len()));
+    }
+    let avg_doc_len = avg_doc_len(&segment);
+    let fields = segment.dict.fields().map(|f| f.to_string()).collect();
+    FlatSegment {
+        arena,
+        index,
+        docs: segment.docs,
+        doc_lengths: segment.doc_lengths,
+        total_docs: segment.total_docs,
+        tokenizer: segment.tokenizer,
+        fields,
+        avg_doc_len,
+    }
+}
+
+pub fn search_flat(segment: &FlatSegment, query: String, size: i32) -> Result<Vec<TopKDoc>, SearchError> {
+    search_flat_with_config(segment, query, size, &SearchConfig::default())
+}
+
+/// Same matching and scoring as `search_with_config` on the segment it was
+/// flattened from.
+pub fn search_flat_with_config(segment: &FlatSegment, query: String, size: i32,
+                               config: &SearchConfig) -> Result<Vec<TopKDoc>, SearchError> {
+    let terms = query_terms(&query, segment.tokenizer.as_deref())?;
+    check_query_terms(&terms, config)?;
+    let iterators: Vec<Iter> = terms.iter()
+        .map(|term| resolve_field_alias(term, &config.field_aliases).unwrap_or_else(|| term.clone()))
+        .filter_map(|term| {
+            let nodes = segment.postings(&term);
+            let doc_freq = match term.split_once(':') {
+                Some((_, value)) if !config.per_field_idf => {
+                    global_doc_freq(value, segment.fields.iter().map(|f| f.as_str()), |t| {
+                        segment.postings(t).iter().map(|node| node.doc_id).collect()
+                    })
+                }
+                _ => nodes.len(),
+            };
+            Iter::from_nodes(PostingIter::Slice(nodes.iter()), doc_freq)
+        })
+        .collect();
+    let stats = CollectionStats {
+        doc_lengths: &segment.doc_lengths,
+        total_docs: segment.total_docs,
+        avg_doc_len: segment.avg_doc_len,
+    };
+    let accept = |doc_id: i64| source_matches(config, segment.docs.get(&doc_id));
+    let mut result = score_iterators(iterators, &stats, size, config, &accept);
+    if config.normalize_scores {
+        normalize_scores(&mut result);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Document, flatten, index_documents, search, search_flat};
+
+    #[test]
+    fn flat_segment_search_success() {
+        let docs: Vec<Document> = (0..50)
+            .map(|id| Document::new(id, &format!("term{} term{} shared common{}", id % 7, id % 3, id % 2)))
+            .collect();
+        let segment = index_documents(docs).expect("");
+        let queries = ["shared", "term1", "term2 term0", "shared common1", "missing", "term1 missing"];
+        let expected: Vec<Vec<(i64, f32)>> = queries.iter()
+            .map(|q| search(&segment, q.to_string(), 20).expect("").iter().map(|d| (d.id, d.score.0)).collect())
+            .collect();
+        crate::assert_sorted(&segment);
+
+        let flat = flatten(segment);
+        assert_eq!(flat.postings("shared").len(), 50);
+        for (query, expected) in queries.iter().zip(expected) {
+            let found: Vec<(i64, f32)> = search_flat(&flat, query.to_string(), 20).expect("")
+                .iter().map(|d| (d.id, d.score.0)).collect();
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::Segment;
+
+/// Query counters shared between the code serving searches and the code
+/// reporting health; clones share the same counters.
+#[derive(Clone)]
+pub struct QueryCounters {
+    pub queries_served: Arc<AtomicU64>,
+    pub total_latency_micros: Arc<AtomicU64>,
+    started: Instant,
+}
+
+impl QueryCounters {
+    pub fn new() -> Self {
+        QueryCounters {
+            queries_served: Arc::new(AtomicU64::new(0)),
+            total_latency_micros: Arc::new(AtomicU64::new(0)),
+            started: Instant::now(),
+        }
+    }
+
+    /// Counts one answered query that took `elapsed`.
+    pub fn record_query(&self, elapsed: Duration) {
+        self.queries_served.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Default for QueryCounters {
+    fn default() -> Self {
+        QueryCounters::new()
+    }
+}
+
+/// Snapshot of a segment and the queries served from it, serializable as the
+/// JSON body of a health endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub status: String,
+    pub total_docs: usize,
+    pub total_unique_terms: usize,
+    pub avg_posting_list_length: f64,
+    pub uptime_seconds: u64,
+    pub queries_served: u64,
+    pub avg_query_latency_ms: f64,
+}
+
+/// Walks every posting list to average their lengths, so the cost grows with
+/// the vocabulary.
+pub fn health_report(segment: &Segment, counters: &QueryCounters) -> HealthReport {
+    let postings: usize = segment.dict.terms()
+        .map(|term| segment.dict.get(term).expect("term listed in dict").len())
+        .sum();
+    let terms = segment.dict.len();
+    let queries = counters.queries_served.load(Ordering::Relaxed);
+    let latency_micros = counters.total_latency_micros.load(Ordering::Relaxed);
+    HealthReport {
+        status: "ok".to_string(),
+        total_docs: segment.total_docs,
+        total_unique_terms: terms,
+        avg_posting_list_length: if terms == 0 { 0.0 } else { postings as f64 / terms as f64 },
+        uptime_seconds: counters.started.elapsed().as_secs(),
+        queries_served: queries,
+        avg_query_latency_ms: if queries == 0 { 0.0 } else { latency_micros as f64 / queries as f64 / 1000.0 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{Document, health_report, index_documents, QueryCounters, search};
+
+    #[test]
+    fn health_report_success() {
+        let segment = index_documents(vec![Document::new(1, "rust search"), Document::new(2, "rust")]).expect("");
+        let counters = QueryCounters::new();
+        let handler_counters = counters.clone();
+        for query in ["rust", "search"] {
+            let started = Instant::now();
+            search(&segment, query.to_string(), 10).expect("");
+            handler_counters.record_query(started.elapsed());
+        }
+        handler_counters.record_query(Duration::from_millis(3));
+
+        let report = health_report(&segment, &counters);
+        let json: serde_json::Value = serde_json::to_value(&report).expect("");
+        for field in ["status", "total_docs", "total_unique_terms", "avg_posting_list_length",
+                      "uptime_seconds", "queries_served", "avg_query_latency_ms"] {
+            assert!(json.get(field).is_some(), "{}", field);
+        }
+        assert_eq!(json["status"], "ok");
+        assert_eq!(report.total_docs, 2);
+        assert_eq!(report.total_unique_terms, 2);
+        assert_eq!(report.avg_posting_list_length, 1.5);
+        assert_eq!(report.queries_served, 3);
+        assert!(report.avg_query_latency_ms >= 1.0);
+        assert!(report.uptime_seconds < 60);
+        crate::assert_sorted(&segment);
+    }
+}
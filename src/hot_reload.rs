@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::{search, Segment, TopKDoc};
+
+/// Serves searches from one segment while a replacement is built elsewhere.
+/// `reload` swaps the segment atomically; searches already running finish on
+/// the segment they started with.
+pub struct HotReloadableIndex {
+    current: ArcSwap<Segment>,
+}
+
+impl HotReloadableIndex {
+    pub fn new(segment: Segment) -> Self {
+        HotReloadableIndex { current: ArcSwap::from_pointee(segment) }
+    }
+
+    pub fn reload(&self, new_segment: Segment) {
+        self.current.store(Arc::new(new_segment));
+    }
+
+    /// The segment currently served.
+    pub fn segment(&self) -> Arc<Segment> {
+        self.current.load_full()
+    }
+
+    /// Searches the current segment without taking a lock. Failed searches,
+    /// such as empty queries, return no hits.
+    pub fn search(&self, query: &str, size: i32) -> Vec<TopKDoc> {
+        let segment = self.current.load();
+        search(&segment, query.to_string(), size).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use crate::{Document, HotReloadableIndex, index_documents};
+
+    #[test]
+    fn hot_reload_during_search_success() {
+        let old = index_documents(vec![Document::new(1, "hello old")]).expect("");
+        let index = Arc::new(HotReloadableIndex::new(old));
+        let reloaded = Arc::new(AtomicBool::new(false));
+
+        let searchers: Vec<_> = (0..4)
+            .map(|_| {
+                let index = Arc::clone(&index);
+                let reloaded = Arc::clone(&reloaded);
+                thread::spawn(move || {
+                    let mut searches = 0;
+                    loop {
+                        let done = reloaded.load(Ordering::Acquire);
+                        let hits = index.search("hello", 10);
+                        assert!(hits.len() == 1 || hits.len() == 2);
+                        searches += 1;
+                        if done {
+                            assert_eq!(hits.len(), 2);
+                            return searches;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let new = index_documents(vec![Document::new(1, "hello old"), Document::new(2, "hello new")]).expect("");
+        index.reload(new);
+        reloaded.store(true, Ordering::Release);
+        for searcher in searchers {
+            assert!(searcher.join().expect("") > 0);
+        }
+
+        assert_eq!(index.search("new", 10)[0].id, 2);
+        assert_eq!(index.segment().total_docs, 2);
+        crate::assert_sorted(&index.segment());
+    }
+}
@@ -0,0 +1,4558 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{btree_map, BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering as AtomicOrdering};
+use std::time::Instant;
+
+use fixedbitset::FixedBitSet;
+use gtrie::Trie;
+use indexmap::IndexMap;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use log::debug;
+use tracing::field::Empty;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[cfg(feature = "tokio")]
+pub use async_io::{flush_to_disk_async, load_from_disk_async};
+pub use boolean::{BooleanQuery, search_boolean};
+pub use caching::CachingSearcher;
+pub use checkpoint::{CheckpointWriter, index_documents_checkpointed, resume_from_checkpoint};
+pub use dict::TermDict;
+pub use flat::{flatten, FlatSegment, search_flat, search_flat_with_config};
+pub use health::{health_report, HealthReport, QueryCounters};
+pub use hot_reload::HotReloadableIndex;
+pub use multi_segment::{compact_smallest_n, MultiSegmentIndex, SegmentEvictionPolicy, shard_documents};
+pub use query::{FuzzyQuery, PhraseQuery, Query, search_phrase, search_query, TermQuery, WildcardQuery};
+pub use trigram::{find_candidates, TrigramIndex};
+pub use writer::{IndexReader, IndexWriter};
+
+pub mod concurrent;
+pub mod eval;
+
+#[cfg(feature = "tokio")]
+mod async_io;
+mod boolean;
+mod caching;
+mod checkpoint;
+mod dict;
+mod flat;
+mod health;
+mod hot_reload;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod multi_segment;
+mod query;
+mod trigram;
+mod writer;
+
+/// Version of the on-disk format, written as the first four bytes of every data file.
+pub const VERSION: u32 = 3;
+
+const PATH_NAME: &str = "index";
+const TERM_DICT_FILE_NAME: &str = "terms_dict.dat";
+const POSTING_LISTS_FILE_NAME: &str = "posting_lists.dat";
+const STORED_DOCS_FILE_NAME: &str = "docs.dat";
+/// Sidecar of the posting lists file: (term hash, offset, node count) sorted by hash.
+const POSTING_INDEX_FILE_NAME: &str = "posting_lists.idx";
+
+#[derive(Debug)]
+pub enum SearchError {
+    Io(std::io::Error),
+    EmptyQuery,
+    /// The segment is still being built and cannot be searched yet.
+    IndexNotReady,
+    /// A `BooleanQuery` has more clauses than `DEFAULT_MAX_QUERY_TERMS`.
+    TooManyClauses(usize),
+    /// The query has more terms than `SearchConfig::max_query_terms` allows.
+    TooManyQueryTerms { count: usize, max: usize },
+    DocumentNotFound(i64),
+    DuplicateDocumentId(i64),
+    /// The estimated size of the segment went over `IndexConfig::max_memory_bytes`.
+    MemoryLimitExceeded { limit: usize, estimated: usize },
+    IncompatibleVersion { found: u32, expected: u32 },
+    /// A posting must record at least one occurrence.
+    InvalidFrequency { term: String, doc_id: i64, freq: i32 },
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Io(e) => write!(f, "i/o error: {}", e),
+            SearchError::EmptyQuery => write!(f, "query has no terms"),
+            SearchError::IndexNotReady => write!(f, "segment is still being built"),
+            SearchError::TooManyClauses(count) => write!(f, "query has too many clauses: {}", count),
+            SearchError::TooManyQueryTerms { count, max } => {
+                write!(f, "query has {} terms, at most {} are allowed", count, max)
+            }
+            SearchError::DocumentNotFound(id) => write!(f, "document {} not found", id),
+            SearchError::DuplicateDocumentId(id) => write!(f, "document id {} appears more than once", id),
+            SearchError::MemoryLimitExceeded { limit, estimated } => {
+                write!(f, "segment needs about {} bytes, over the {} byte budget", estimated, limit)
+            }
+            SearchError::IncompatibleVersion { found, expected } => {
+                write!(f, "incompatible index version {}, expected {}", found, expected)
+            }
+            SearchError::InvalidFrequency { term, doc_id, freq } => {
+                write!(f, "posting of {:?} in document {} has frequency {}", term, doc_id, freq)
+            }
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SearchError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SearchError {
+    fn from(e: std::io::Error) -> Self {
+        SearchError::Io(e)
+    }
+}
+
+pub struct Segment {
+    pub dict: TermDict,
+    pub docs: HashMap<i64, Arc<Document>>,
+    pub doc_lengths: HashMap<i64, u32>,
+    /// Number of stored documents, kept in sync with `docs` by every mutation.
+    pub total_docs: usize,
+    /// Tokens processed by `index_documents` and `add_document`; never decremented.
+    pub total_tokens_indexed: u64,
+    /// When the segment was built or loaded; used by segment eviction.
+    pub created_at: Instant,
+    /// Set once indexing has finished; searches are refused until then.
+    pub built: AtomicBool,
+    /// Tokenizer the documents were indexed with, reused when documents are
+    /// added or deleted later. `None` means `NaiveTokenizer`.
+    pub tokenizer: Option<Arc<IndexTokenizer>>,
+    /// LZ4-compressed `Document::text` of every stored document, for segments
+    /// built with `IndexConfig::compress_document_store`. The documents in
+    /// `docs` then hold an empty text; read them through `Segment::document`.
+    pub compressed_texts: Option<HashMap<i64, Vec<u8>>>,
+    /// Trigrams of every indexed term, for segments built with
+    /// `IndexConfig::build_trigram_index`, kept up to date by indexing and
+    /// `apply_tx`. Deleting documents does not prune it: it may still list
+    /// terms deleted since, so look candidates up in `dict` before use.
+    pub trigrams: Option<TrigramIndex>,
+}
+
+impl Segment {
+    pub fn new() -> Self {
+        Segment {
+            dict: TermDict::new(),
+            docs: HashMap::new(),
+            doc_lengths: HashMap::new(),
+            total_docs: 0,
+            total_tokens_indexed: 0,
+            created_at: Instant::now(),
+            built: AtomicBool::new(false),
+            tokenizer: None,
+            compressed_texts: None,
+            trigrams: None,
+        }
+    }
+
+    /// Empty segment with room for `capacity` documents.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut segment = Segment::new();
+        segment.docs.reserve(capacity);
+        segment.doc_lengths.reserve(capacity);
+        segment
+    }
+
+    pub fn is_built(&self) -> bool {
+        self.built.load(AtomicOrdering::Acquire)
+    }
+
+    /// Stored document with its text, decompressed if the document store is
+    /// compressed.
+    pub fn document(&self, doc_id: i64) -> Option<Arc<Document>> {
+        let document = self.docs.get(&doc_id)?;
+        match self.compressed_texts.as_ref().and_then(|texts| texts.get(&doc_id)) {
+            Some(bytes) => Some(Arc::new(Document { text: decompress_text(bytes), ..document.as_ref().clone() })),
+            None => Some(document.clone()),
+        }
+    }
+
+    /// Builds a searchable segment straight from `(term, doc_id, freq)` triples,
+    /// bypassing tokenization. Repeated pairs add up their frequencies. Every
+    /// doc_id gets an empty stored document and a length equal to the sum of its
+    /// frequencies, which is what `index_documents` would have recorded. With no
+    /// text to re-tokenize, deleting one of them walks the whole dictionary.
+    ///
+    /// Fails with `InvalidFrequency` on a freq that is not positive or that
+    /// overflows once added to the earlier ones of the same pair.
+    pub fn from_triples(triples: impl Iterator<Item = (String, i64, i32)>) -> Result<Segment, SearchError> {
+        let mut postings: HashMap<String, PostingList> = HashMap::new();
+        let mut segment = Segment::new();
+        for (term, doc_id, freq) in triples {
+            if freq <= 0 {
+                return Err(SearchError::InvalidFrequency { term, doc_id, freq });
+            }
+            if !postings.contains_key(&term) {
+                postings.insert(term.clone(), PostingList::default());
+            }
+            let posting = postings.get_mut(&term).expect("term just inserted");
+            if posting.get(doc_id).is_some_and(|node| node.freq.checked_add(freq).is_none()) {
+                return Err(SearchError::InvalidFrequency { term, doc_id, freq });
+            }
+            posting.add(doc_id, freq);
+            let doc_len = segment.doc_lengths.entry(doc_id).or_insert(0);
+            *doc_len = doc_len.saturating_add(freq as u32);
+            segment.total_tokens_indexed = segment.total_tokens_indexed.saturating_add(freq as u64);
+        }
+        for &doc_id in segment.doc_lengths.keys() {
+            segment.docs.insert(doc_id, Arc::new(Document::new(doc_id, "")));
+        }
+        segment.total_docs = segment.docs.len();
+        let terms: Vec<String> = postings.keys().cloned().collect();
+        for (term, posting) in postings {
+            segment.dict.insert(&term, posting);
+        }
+        upgrade_frequent_postings(&mut segment, terms);
+        segment.built.store(true, AtomicOrdering::Release);
+        Ok(segment)
+    }
+}
+
+// AtomicBool is not Clone, so the flag is copied by value.
+impl Clone for Segment {
+    fn clone(&self) -> Self {
+        Segment {
+            dict: self.dict.clone(),
+            docs: self.docs.clone(),
+            doc_lengths: self.doc_lengths.clone(),
+            total_docs: self.total_docs,
+            total_tokens_indexed: self.total_tokens_indexed,
+            created_at: self.created_at,
+            built: AtomicBool::new(self.is_built()),
+            tokenizer: self.tokenizer.clone(),
+            compressed_texts: self.compressed_texts.clone(),
+            trigrams: self.trigrams.clone(),
+        }
+    }
+}
+
+impl Default for Segment {
+    fn default() -> Self {
+        Segment::new()
+    }
+}
+
+impl fmt::Debug for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let first_terms: Vec<&str> = self.dict.terms().take(5).collect();
+        f.debug_struct("Segment")
+            .field("total_docs", &self.total_docs)
+            .field("unique_terms", &self.dict.len())
+            .field("first_terms", &first_terms)
+            .finish()
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Segment with {} documents and {} unique terms", self.total_docs, self.dict.len())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub id: i64,
+    pub text: String,
+    /// Named fields, each holding one or more values. Every value is tokenized and
+    /// indexed as `name:token`, so the query `category:books` matches any document
+    /// whose `category` field contains "books".
+    pub fields: HashMap<String, Vec<String>>,
+    /// Where the document came from, e.g. "web" or "pdf", see `SearchConfig::source_filter`.
+    /// Not indexed.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+impl Document {
+    pub fn new(id: i64, text: &str) -> Self {
+        Document { id, text: text.to_string(), fields: HashMap::new(), source: None }
+    }
+}
+
+/// Display template for search results, e.g. "{{title}} by {{author}}".
+#[derive(Clone, Debug)]
+pub struct ResultTemplate {
+    pub template: String,
+}
+
+impl ResultTemplate {
+    pub fn new(template: &str) -> Self {
+        ResultTemplate { template: template.to_string() }
+    }
+}
+
+/// Replaces every `{{field_name}}` with the values of that document field,
+/// joined by ", ". Missing fields render as nothing; an unclosed `{{` is kept.
+pub fn render(template: &ResultTemplate, document: &Document) -> String {
+    let mut out = String::with_capacity(template.template.len());
+    let mut rest = template.template.as_str();
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + len].trim();
+        if let Some(values) = document.fields.get(name) {
+            out.push_str(&values.join(", "));
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[derive(Clone)]
+pub struct PostingNode {
+    pub doc_id: i64,
+    pub freq: i32,
+}
+
+/// Storage of a posting list.
+#[derive(Clone)]
+pub enum PostingListRepr {
+    /// One node per document keyed by doc_id, so single-document lookups and
+    /// updates are O(log n).
+    Sparse(BTreeMap<i64, PostingNode>),
+    /// One bit per doc_id, for terms found in most documents. Frequencies are
+    /// only kept for the documents where they are not 1.
+    Dense { docs: FixedBitSet, freqs: HashMap<i64, i32> },
+}
+
+/// Postings of one term, iterated in doc_id order whatever the representation.
+#[derive(Clone)]
+pub struct PostingList {
+    pub repr: PostingListRepr,
+}
+
+impl PostingList {
+    pub fn new() -> Self {
+        PostingList { repr: PostingListRepr::Sparse(BTreeMap::new()) }
+    }
+
+    /// Number of documents containing the term.
+    pub fn len(&self) -> usize {
+        match &self.repr {
+            PostingListRepr::Sparse(nodes) => nodes.len(),
+            PostingListRepr::Dense { docs, .. } => docs.count_ones(..),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, doc_id: i64) -> Option<PostingNode> {
+        match &self.repr {
+            PostingListRepr::Sparse(nodes) => nodes.get(&doc_id).cloned(),
+            PostingListRepr::Dense { docs, freqs } => {
+                if doc_id < 0 || !docs.contains(doc_id as usize) {
+                    return None;
+                }
+                Some(PostingNode { doc_id, freq: freqs.get(&doc_id).copied().unwrap_or(1) })
+            }
+        }
+    }
+
+    pub fn contains(&self, doc_id: i64) -> bool {
+        self.get(doc_id).is_some()
+    }
+
+    /// Adds `freq` occurrences of the term in `doc_id`. A bitset that would have
+    /// to grow past `max_dense_bits` for it goes back to the node list.
+    pub fn add(&mut self, doc_id: i64, freq: i32) {
+        let limit = match &self.repr {
+            PostingListRepr::Dense { docs, .. } if doc_id < 0 || doc_id as usize >= docs.len() => {
+                Some(max_dense_bits(self.len() + 1))
+            }
+            _ => None,
+        };
+        if limit.is_some_and(|limit| doc_id < 0 || doc_id as usize >= limit) {
+            self.make_sparse();
+        }
+        match &mut self.repr {
+            PostingListRepr::Sparse(nodes) => {
+                nodes.entry(doc_id).or_insert(PostingNode { doc_id, freq: 0 }).freq += freq;
+            }
+            PostingListRepr::Dense { docs, freqs } => {
+                let bit = doc_id as usize;
+                if let Some(limit) = limit {
+                    // doubling keeps appends of increasing doc_ids from regrowing on every call
+                    docs.grow((bit + 1).max(docs.len() * 2).min(limit));
+                }
+                let total = if docs.put(bit) { freqs.get(&doc_id).copied().unwrap_or(1) + freq } else { freq };
+                if total == 1 {
+                    freqs.remove(&doc_id);
+                } else {
+                    freqs.insert(doc_id, total);
+                }
+            }
+        }
+    }
+
+    /// Removes the posting of `doc_id`, returning false if there was none.
+    pub fn remove(&mut self, doc_id: i64) -> bool {
+        match &mut self.repr {
+            PostingListRepr::Sparse(nodes) => nodes.remove(&doc_id).is_some(),
+            PostingListRepr::Dense { docs, freqs } => {
+                if doc_id < 0 || !docs.contains(doc_id as usize) {
+                    return false;
+                }
+                docs.set(doc_id as usize, false);
+                freqs.remove(&doc_id);
+                true
+            }
+        }
+    }
+
+    /// Nodes in doc_id order.
+    pub fn nodes(&self) -> PostingIter<'_> {
+        match &self.repr {
+            PostingListRepr::Sparse(nodes) => PostingIter::Sparse(nodes.values()),
+            PostingListRepr::Dense { docs, freqs } => PostingIter::Dense { ones: docs.ones(), freqs },
+        }
+    }
+
+    pub fn doc_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.nodes().map(|node| node.doc_id)
+    }
+
+    pub fn is_dense(&self) -> bool {
+        matches!(self.repr, PostingListRepr::Dense { .. })
+    }
+
+    pub fn make_sparse(&mut self) {
+        if self.is_dense() {
+            self.repr = PostingListRepr::Sparse(self.nodes().map(|node| (node.doc_id, node)).collect());
+        }
+    }
+
+    /// Switches to the bitset, unless some doc_id is negative and cannot be a bit.
+    pub fn make_dense(&mut self) -> bool {
+        if let PostingListRepr::Sparse(nodes) = &self.repr {
+            if nodes.keys().next().is_some_and(|&first| first < 0) {
+                return false;
+            }
+            let bits = nodes.keys().next_back().map(|&last| last as usize + 1).unwrap_or(0);
+            let mut docs = FixedBitSet::with_capacity(bits);
+            let mut freqs = HashMap::new();
+            for node in nodes.values() {
+                docs.insert(node.doc_id as usize);
+                if node.freq != 1 {
+                    freqs.insert(node.doc_id, node.freq);
+                }
+            }
+            self.repr = PostingListRepr::Dense { docs, freqs };
+        }
+        true
+    }
+
+    /// Switches to the bitset once the term is in more than half of `total_docs`
+    /// documents, provided the bitset is no larger than the node list it replaces.
+    /// Returns true if the representation changed.
+    pub fn upgrade_if_frequent(&mut self, total_docs: usize) -> bool {
+        let nodes = match &self.repr {
+            PostingListRepr::Sparse(nodes) => nodes,
+            PostingListRepr::Dense { .. } => return false,
+        };
+        let max_doc_id = match nodes.keys().next_back() {
+            Some(&last) => last,
+            None => return false,
+        };
+        let bitset_bytes = (max_doc_id as u64 / 64 + 1) * 8;
+        if nodes.len() * 2 <= total_docs || bitset_bytes > (nodes.len() * POSTING_NODE_SIZE) as u64 {
+            return false;
+        }
+        self.make_dense()
+    }
+}
+
+/// Widest bitset a dense posting list of `len` documents may keep: twice the
+/// bytes of the node list it replaces, see `PostingList::upgrade_if_frequent`.
+fn max_dense_bits(len: usize) -> usize {
+    len * POSTING_NODE_SIZE * 8 * 2
+}
+
+impl Default for PostingList {
+    fn default() -> Self {
+        PostingList::new()
+    }
+}
+
+impl FromIterator<PostingNode> for PostingList {
+    fn from_iter<I: IntoIterator<Item = PostingNode>>(nodes: I) -> Self {
+        let mut posting = PostingList::new();
+        for node in nodes {
+            posting.add(node.doc_id, node.freq);
+        }
+        posting
+    }
+}
+
+/// Iterator over the nodes of a `PostingList`, see `PostingList::nodes`.
+pub enum PostingIter<'a> {
+    Sparse(btree_map::Values<'a, i64, PostingNode>),
+    /// Nodes stored contiguously, as in a `FlatSegment` arena.
+    Slice(std::slice::Iter<'a, PostingNode>),
+    Dense { ones: fixedbitset::Ones<'a>, freqs: &'a HashMap<i64, i32> },
+}
+
+impl Iterator for PostingIter<'_> {
+    type Item = PostingNode;
+
+    fn next(&mut self) -> Option<PostingNode> {
+        match self {
+            PostingIter::Sparse(nodes) => nodes.next().cloned(),
+            PostingIter::Slice(nodes) => nodes.next().cloned(),
+            PostingIter::Dense { ones, freqs } => ones.next().map(|bit| {
+                let doc_id = bit as i64;
+                PostingNode { doc_id, freq: freqs.get(&doc_id).copied().unwrap_or(1) }
+            }),
+        }
+    }
+}
+
+/// Nodes of a posting list, minus those of documents listed in `tombstones`.
+/// Useful when deletions are recorded on the side, e.g. while postings
+/// loaded from disk are still shared with a reader.
+pub struct LiveIter<'a> {
+    inner: PostingIter<'a>,
+    tombstones: &'a HashSet<i64>,
+}
+
+impl<'a> LiveIter<'a> {
+    pub fn new(posting: &'a PostingList, tombstones: &'a HashSet<i64>) -> Self {
+        LiveIter { inner: posting.nodes(), tombstones }
+    }
+}
+
+impl Iterator for LiveIter<'_> {
+    type Item = PostingNode;
+
+    fn next(&mut self) -> Option<PostingNode> {
+        let tombstones = self.tombstones;
+        self.inner.find(|node| !tombstones.contains(&node.doc_id))
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub struct F32(pub f32);
+
+impl Eq for F32 {}
+
+impl PartialOrd for F32 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for F32 {
+    fn cmp(&self, other: &F32) -> Ordering {
+        other.0.partial_cmp(&self.0).unwrap()
+    }
+}
+
+/// A search hit. Equality and hashing look at the document id only, so hits for
+/// the same document from different scoring passes collapse in a `HashSet`.
+/// Hits order by score; equal scores order by doc_id, lower ids ranking higher,
+/// so ties come out the same way however the hits were collected.
+#[derive(Copy, Clone)]
+pub struct TopKDoc {
+    pub id: i64,
+    pub score: F32,
+}
+
+impl TopKDoc {
+    /// True if both hits point at the same document with the same score.
+    pub fn same_hit(&self, other: &TopKDoc) -> bool {
+        self.id == other.id && self.score == other.score
+    }
+}
+
+impl PartialEq for TopKDoc {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Hash for TopKDoc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialOrd<Self> for TopKDoc {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TopKDoc {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.score.cmp(&self.score).then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl Eq for TopKDoc {}
+
+pub trait Tokenizer {
+    /// Tokens of `text` with their 0-based word offset.
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)>;
+
+    /// Takes the tokenizer by value and returns positions as i32.
+    #[deprecated(note = "use tokenize_with_positions")]
+    fn tokenize(self, text: String) -> Vec<(String, i32)> where Self: Sized {
+        self.tokenize_with_positions(&text).into_iter().map(|(token, pos)| (token, pos as i32)).collect()
+    }
+
+    /// Distinct tokens emitted so far, for tokenizers that keep count; see
+    /// `TrackingTokenizer`.
+    fn vocabulary_size(&mut self) -> usize {
+        0
+    }
+
+    /// Terms a search for `text` looks up. Search is conjunctive, so
+    /// tokenizers emitting alternatives for the same text override this to
+    /// keep a single reading.
+    fn query_tokens(&self, text: &str) -> Vec<String> {
+        self.tokenize_with_positions(text).into_iter().map(|(token, _)| token).collect()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct NaiveTokenizer {}
+
+impl NaiveTokenizer {
+    pub fn new() -> Self {
+        NaiveTokenizer {}
+    }
+}
+
+impl Tokenizer for NaiveTokenizer {
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        text.split_whitespace().zip(0..).map(|(token, pos)| (token.to_string(), pos)).collect()
+    }
+}
+
+/// Lowercases every token of `NaiveTokenizer`, for case-insensitive matching.
+#[derive(Clone, Default)]
+pub struct LowercaseTokenizer {}
+
+impl LowercaseTokenizer {
+    pub fn new() -> Self {
+        LowercaseTokenizer {}
+    }
+}
+
+impl Tokenizer for LowercaseTokenizer {
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        NaiveTokenizer::new().tokenize_with_positions(text).into_iter()
+            .map(|(token, pos)| (token.to_lowercase(), pos))
+            .collect()
+    }
+}
+
+/// Splits text into words following Unicode word boundaries (UAX #29), dropping
+/// punctuation and whitespace. Numbers such as "3.14" or "1,000" stay whole.
+/// Hyphenated words like "state-of-the-art" are kept as one token and
+/// contractions like "can't" as one token unless the matching flag is set.
+#[derive(Clone, Default)]
+pub struct WordBoundaryTokenizer {
+    pub split_hyphens: bool,
+    pub split_apostrophes: bool,
+}
+
+impl Tokenizer for WordBoundaryTokenizer {
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        let mut words: Vec<String> = Vec::new();
+        let mut after_word = false;
+        // a single hyphen right after a word, glued on if another word follows
+        let mut hyphen = false;
+        for segment in text.split_word_bounds() {
+            if segment.chars().any(char::is_alphanumeric) {
+                match words.last_mut() {
+                    Some(last) if hyphen => {
+                        last.push('-');
+                        last.push_str(segment);
+                    }
+                    _ => words.push(segment.to_string()),
+                }
+                hyphen = false;
+                after_word = true;
+            } else {
+                hyphen = !self.split_hyphens && after_word && segment == "-";
+                after_word = false;
+            }
+        }
+        if self.split_apostrophes {
+            words = words.iter()
+                .flat_map(|word| word.split(['\'', '\u{2019}']))
+                .filter(|part| !part.is_empty())
+                .map(|part| part.to_string())
+                .collect();
+        }
+        words.into_iter().zip(0..).collect()
+    }
+}
+
+/// Wraps another tokenizer and remembers every distinct token it emitted, to
+/// watch the vocabulary grow while a corpus is indexed.
+pub struct TrackingTokenizer<T: Tokenizer> {
+    pub inner: T,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl<T: Tokenizer> TrackingTokenizer<T> {
+    pub fn new(inner: T) -> Self {
+        TrackingTokenizer { inner, seen: Mutex::new(HashSet::new()) }
+    }
+}
+
+impl<T: Tokenizer> Tokenizer for TrackingTokenizer<T> {
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        let tokens = self.inner.tokenize_with_positions(text);
+        let mut seen = self.seen.lock().unwrap();
+        for (token, _) in &tokens {
+            if !seen.contains(token) {
+                seen.insert(token.clone());
+            }
+        }
+        tokens
+    }
+
+    fn vocabulary_size(&mut self) -> usize {
+        self.seen.get_mut().unwrap().len()
+    }
+
+    /// Query terms are not counted into the vocabulary.
+    fn query_tokens(&self, text: &str) -> Vec<String> {
+        self.inner.query_tokens(text)
+    }
+}
+
+/// Cost of a segmentation piece that is not in the vocabulary: one character,
+/// priced so that any known word is preferred over spelling it out.
+const UNKNOWN_CHAR_COST: usize = 3;
+
+/// The `n` best ways to split `text` into words of `dict`, best first, for
+/// scripts written without spaces. Fewer pieces are more likely; characters
+/// no word covers become single-character pieces at `UNKNOWN_CHAR_COST`.
+/// Ties are broken by comparing the pieces, so the order is deterministic.
+pub fn tokenize_n_best(text: &str, n: usize, dict: &Trie<char, ()>) -> Vec<Vec<String>> {
+    let chars: Vec<char> = text.chars().collect();
+    if n == 0 || chars.is_empty() {
+        return Vec::new();
+    }
+    // best[i]: the n cheapest segmentations of chars[..i]; keeping n per prefix
+    // is enough, since extending a segmentation adds the same cost to all of them
+    let mut best: Vec<Vec<(usize, Vec<String>)>> = vec![Vec::new(); chars.len() + 1];
+    best[0].push((0, Vec::new()));
+    for end in 1..=chars.len() {
+        let mut candidates = Vec::new();
+        for start in 0..end {
+            let piece = &chars[start..end];
+            let cost = if dict.contains_key(piece.iter().copied()) {
+                1
+            } else if piece.len() == 1 {
+                UNKNOWN_CHAR_COST
+            } else {
+                continue;
+            };
+            let word: String = piece.iter().collect();
+            for (prefix_cost, prefix) in &best[start] {
+                let mut tokens = prefix.clone();
+                tokens.push(word.clone());
+                candidates.push((prefix_cost + cost, tokens));
+            }
+        }
+        candidates.sort();
+        candidates.dedup();
+        candidates.truncate(n);
+        best[end] = candidates;
+    }
+    best.pop().expect("one entry per prefix").into_iter().map(|(_, tokens)| tokens).collect()
+}
+
+/// Indexes every one of the `n` best segmentations of each whitespace-separated
+/// chunk, see `tokenize_n_best`, so a query matches whichever reading it uses.
+/// Positions count the pieces of each segmentation from the start of its chunk,
+/// which itself starts after the pieces of the best segmentation of the chunks
+/// before; a piece shared by several segmentations is emitted once.
+pub struct NBestTokenizer {
+    pub dict: Trie<char, ()>,
+    pub n: usize,
+}
+
+impl NBestTokenizer {
+    pub fn new(vocabulary: &[&str], n: usize) -> Self {
+        let mut dict = Trie::new();
+        for word in vocabulary {
+            dict.insert(word.chars(), ());
+        }
+        NBestTokenizer { dict, n }
+    }
+}
+
+impl Tokenizer for NBestTokenizer {
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        let mut result = Vec::new();
+        let mut emitted = HashSet::new();
+        let mut offset: u32 = 0;
+        for chunk in text.split_whitespace() {
+            let segmentations = tokenize_n_best(chunk, self.n, &self.dict);
+            for tokens in &segmentations {
+                for (token, pos) in tokens.iter().zip(offset..) {
+                    if emitted.insert((token.clone(), pos)) {
+                        result.push((token.clone(), pos));
+                    }
+                }
+            }
+            offset += segmentations.first().map_or(0, |tokens| tokens.len() as u32);
+        }
+        result
+    }
+
+    /// Only the best segmentation: every reading of a document is indexed, so
+    /// it matches whichever reading the document also has.
+    fn query_tokens(&self, text: &str) -> Vec<String> {
+        text.split_whitespace()
+            .flat_map(|chunk| tokenize_n_best(chunk, 1, &self.dict).into_iter().flatten())
+            .collect()
+    }
+}
+
+/// Tokenizer shared by an `IndexConfig` and the segments built with it.
+pub type IndexTokenizer = dyn Tokenizer + Send + Sync;
+
+/// Tokenizes text straight from a reader, for documents too large to hold in
+/// one `String`.
+pub trait StreamingTokenizer {
+    fn tokenize_stream<R: Read>(&self, reader: R) -> Vec<(String, u32)>;
+}
+
+const STREAM_CHUNK_SIZE: usize = 8 * 1024;
+
+impl StreamingTokenizer for NaiveTokenizer {
+    /// Reads the stream in chunks and only splits up to the last ASCII whitespace
+    /// seen, so tokens and multi-byte characters spanning two chunks stay whole.
+    /// Reading stops at the first I/O error.
+    fn tokenize_stream<R: Read>(&self, mut reader: R) -> Vec<(String, u32)> {
+        let mut result = Vec::new();
+        let mut pending: Vec<u8> = Vec::new();
+        let mut chunk = [0u8; STREAM_CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            pending.extend_from_slice(&chunk[..read]);
+            if let Some(boundary) = pending.iter().rposition(|b| b.is_ascii_whitespace()) {
+                let rest = pending.split_off(boundary + 1);
+                push_tokens(&mut result, &pending);
+                pending = rest;
+            }
+        }
+        push_tokens(&mut result, &pending);
+        result
+    }
+}
+
+fn push_tokens(result: &mut Vec<(String, u32)>, bytes: &[u8]) {
+    for token in String::from_utf8_lossy(bytes).split_whitespace() {
+        result.push((token.to_string(), result.len() as u32));
+    }
+}
+
+/// Unicode normalization form applied by `UnicodeNormalizer`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NormalizationForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+/// Wraps another tokenizer and normalizes each of its tokens, so visually equal
+/// terms with different encodings map to the same dictionary entry.
+pub struct UnicodeNormalizer<T: Tokenizer> {
+    pub inner: T,
+    pub form: NormalizationForm,
+}
+
+impl<T: Tokenizer> UnicodeNormalizer<T> {
+    pub fn new(inner: T, form: NormalizationForm) -> Self {
+        UnicodeNormalizer { inner, form }
+    }
+
+    fn normalize(&self, token: &str) -> String {
+        match self.form {
+            NormalizationForm::Nfc => token.nfc().collect(),
+            NormalizationForm::Nfd => token.nfd().collect(),
+            NormalizationForm::Nfkc => token.nfkc().collect(),
+            NormalizationForm::Nfkd => token.nfkd().collect(),
+        }
+    }
+}
+
+impl<T: Tokenizer> Tokenizer for UnicodeNormalizer<T> {
+    fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        self.inner.tokenize_with_positions(text).into_iter()
+            .map(|(token, pos)| (self.normalize(&token), pos))
+            .collect()
+    }
+
+    fn query_tokens(&self, text: &str) -> Vec<String> {
+        self.inner.query_tokens(text).iter().map(|token| self.normalize(token)).collect()
+    }
+}
+
+pub fn init() -> Result<(), SearchError> {
+    fs::create_dir_all(PATH_NAME)?;
+    File::create(format!("{}/{}", PATH_NAME, TERM_DICT_FILE_NAME))?;
+    File::create(format!("{}/{}", PATH_NAME, POSTING_LISTS_FILE_NAME))?;
+    Ok(())
+}
+
+/// Decides whether a term goes into the index.
+pub trait TermFilter: Send + Sync {
+    fn accept(&self, term: &str) -> bool;
+}
+
+/// Assigns the id of a document when it is indexed, see `IndexConfig::id_generator`.
+pub trait DocIdGenerator: Send + Sync {
+    fn generate(&self, document: &Document) -> i64;
+}
+
+/// Hands out `start`, `start + 1`, ... in the order documents are indexed.
+pub struct SequentialIdGenerator {
+    next: AtomicI64,
+}
+
+impl SequentialIdGenerator {
+    pub fn new(start: i64) -> Self {
+        SequentialIdGenerator { next: AtomicI64::new(start) }
+    }
+}
+
+impl DocIdGenerator for SequentialIdGenerator {
+    fn generate(&self, _document: &Document) -> i64 {
+        self.next.fetch_add(1, AtomicOrdering::Relaxed)
+    }
+}
+
+/// 64-bit FNV-1a hash of the document text, so the same text always gets the
+/// same id and re-adding it replaces the stored copy.
+pub struct ContentHashIdGenerator;
+
+impl DocIdGenerator for ContentHashIdGenerator {
+    fn generate(&self, document: &Document) -> i64 {
+        term_hash(&document.text) as i64
+    }
+}
+
+/// Index-time options for `index_documents_with_config`.
+#[derive(Default)]
+pub struct IndexConfig {
+    /// Terms rejected by the filter are not added to the dictionary.
+    pub term_filter: Option<Box<dyn TermFilter>>,
+    /// Estimated size above which `index_documents_with_config` stops with
+    /// `MemoryLimitExceeded`, see `estimated_memory_bytes`.
+    pub max_memory_bytes: Option<usize>,
+    /// Tokenizer for `index_documents_with_config`; `NaiveTokenizer` if unset.
+    /// The segment keeps it, so documents added to it later are tokenized the
+    /// same way whatever config `add_document_with_config` gets.
+    pub tokenizer: Option<Arc<IndexTokenizer>>,
+    /// Record binary occurrence: every posting gets freq 1 however often the
+    /// token appears in the document.
+    pub deduplicate_tokens: bool,
+    /// Keep stored texts LZ4-compressed, see `Segment::compressed_texts`. Saves
+    /// memory on large corpora at the cost of decompressing on every access.
+    pub compress_document_store: bool,
+    /// Overwrites the id of every document indexed or added with this config.
+    pub id_generator: Option<Box<dyn DocIdGenerator>>,
+    /// Keep a `TrigramIndex` of the terms in `Segment::trigrams`, which
+    /// `search_fuzzy` then uses instead of scanning the whole dictionary.
+    pub build_trigram_index: bool,
+}
+
+pub fn index_documents(documents: Vec<Document>) -> Result<Segment, SearchError> {
+    index_documents_with_config(documents, &IndexConfig::default())
+}
+
+/// Fails with `DuplicateDocumentId` before indexing anything if two documents
+/// share an id.
+pub fn index_documents_with_config(documents: Vec<Document>, config: &IndexConfig) -> Result<Segment, SearchError> {
+    index_into(Segment::new(), documents, config)
+}
+
+/// Like `index_documents_with_config`, with the per-document maps sized for
+/// `capacity` documents up front so bulk ingestion does not keep regrowing them.
+pub fn index_documents_with_capacity(documents: Vec<Document>, capacity: usize,
+                                     config: IndexConfig) -> Result<Segment, SearchError> {
+    index_into(Segment::with_capacity(capacity), documents, &config)
+}
+
+fn index_into(mut segment: Segment, mut documents: Vec<Document>,
+              config: &IndexConfig) -> Result<Segment, SearchError> {
+    if let Some(generator) = &config.id_generator {
+        for document in &mut documents {
+            document.id = generator.generate(document);
+        }
+    }
+    let mut ids = HashSet::with_capacity(documents.len());
+    if let Some(document) = documents.iter().find(|document| !ids.insert(document.id)) {
+        return Err(SearchError::DuplicateDocumentId(document.id));
+    }
+
+    let started = Instant::now();
+    #[cfg(feature = "metrics")]
+    let (docs_before, tokens_before) = (segment.total_docs, segment.total_tokens_indexed);
+    segment.tokenizer = config.tokenizer.clone();
+    if config.compress_document_store {
+        segment.compressed_texts.get_or_insert_with(HashMap::new);
+    }
+    if config.build_trigram_index {
+        segment.trigrams.get_or_insert_with(TrigramIndex::new);
+    }
+    let mut doc_bytes = 0;
+    let mut posting_entries = 0;
+    for document in documents {
+        doc_bytes += document_size(&document);
+        posting_entries += index_document(&mut segment, document, config);
+        if let Some(limit) = config.max_memory_bytes {
+            let estimated = estimated_memory_bytes(segment.docs.len(), doc_bytes, posting_entries);
+            if estimated > limit {
+                return Err(SearchError::MemoryLimitExceeded { limit, estimated });
+            }
+        }
+    }
+    let terms: Vec<String> = segment.dict.terms().map(|t| t.to_string()).collect();
+    upgrade_frequent_postings(&mut segment, terms);
+    segment.built.store(true, AtomicOrdering::Release);
+    debug!("indexed {} documents, {} tokens into {} terms in {:?}",
+           segment.total_docs, segment.total_tokens_indexed, segment.dict.len(), started.elapsed());
+    #[cfg(feature = "metrics")]
+    metrics::record_indexed(segment.total_docs - docs_before, segment.total_tokens_indexed - tokens_before,
+                            segment.total_docs);
+
+    Ok(segment)
+}
+
+/// Moves the posting lists of `terms` to the bitset representation where
+/// `PostingList::upgrade_if_frequent` allows it.
+fn upgrade_frequent_postings(segment: &mut Segment, terms: impl IntoIterator<Item = String>) {
+    for term in terms {
+        if let Some(posting) = segment.dict.get_mut(&term) {
+            posting.upgrade_if_frequent(segment.total_docs);
+        }
+    }
+}
+
+/// Indexes every regular file in `path` as one document. Files are read in name
+/// order and numbered from 0, so the same directory always yields the same ids.
+pub fn index_directory(path: &str) -> Result<Segment, SearchError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    let mut documents = Vec::with_capacity(files.len());
+    for (id, file) in files.iter().enumerate() {
+        documents.push(Document::new(id as i64, &fs::read_to_string(file)?));
+    }
+    index_documents(documents)
+}
+
+/// Adds a document to an existing segment. A document already stored under the
+/// same id is replaced.
+pub fn add_document(segment: &mut Segment, document: Document) -> Result<(), SearchError> {
+    add_document_with_config(segment, document, &IndexConfig::default())
+}
+
+pub fn add_document_with_config(segment: &mut Segment, mut document: Document,
+                                config: &IndexConfig) -> Result<(), SearchError> {
+    if let Some(generator) = &config.id_generator {
+        document.id = generator.generate(&document);
+    }
+    if segment.docs.contains_key(&document.id) {
+        delete_document(segment, document.id)?;
+    }
+    let terms: HashSet<String> = document_tokens(segment, &document).into_iter().map(|(token, _)| token).collect();
+    #[cfg(feature = "metrics")]
+    let tokens_before = segment.total_tokens_indexed;
+    index_document(segment, document, config);
+    upgrade_frequent_postings(segment, terms);
+    segment.built.store(true, AtomicOrdering::Release);
+    #[cfg(feature = "metrics")]
+    metrics::record_indexed(1, segment.total_tokens_indexed - tokens_before, segment.total_docs);
+    Ok(())
+}
+
+/// Rebuilds the segment from its stored documents with `config`, e.g. after the
+/// tokenizer changed. The old dictionary is dropped.
+pub fn reindex(segment: Segment, config: IndexConfig) -> Result<Segment, SearchError> {
+    let texts = segment.compressed_texts.unwrap_or_default();
+    let mut documents: Vec<Document> = segment.docs.into_iter()
+        .map(|(doc_id, doc)| {
+            let mut doc = Arc::try_unwrap(doc).unwrap_or_else(|shared| shared.as_ref().clone());
+            if let Some(bytes) = texts.get(&doc_id) {
+                doc.text = decompress_text(bytes);
+            }
+            doc
+        })
+        .collect();
+    documents.sort_by_key(|doc| doc.id);
+    index_documents_with_config(documents, &config)
+}
+
+/// Removes a document and its entries from every posting list; terms left
+/// without postings are dropped from the dictionary.
+pub fn delete_document(segment: &mut Segment, doc_id: i64) -> Result<(), SearchError> {
+    let mut emptied = HashSet::new();
+    remove_document(segment, doc_id, &mut emptied)?;
+    segment.dict.retain(|term| !emptied.contains(term));
+    Ok(())
+}
+
+/// Deletes every document for which `predicate` holds and returns how many
+/// were removed. The dictionary is pruned once at the end, not per document.
+pub fn delete_where(segment: &mut Segment, predicate: impl Fn(&Document) -> bool) -> usize {
+    let doc_ids: Vec<i64> = segment.docs.keys()
+        .filter(|&&doc_id| predicate(&segment.document(doc_id).expect("doc_id taken from segment.docs")))
+        .copied()
+        .collect();
+    let mut emptied = HashSet::new();
+    for &doc_id in &doc_ids {
+        remove_document(segment, doc_id, &mut emptied).expect("doc_id taken from segment.docs");
+    }
+    segment.dict.retain(|term| !emptied.contains(term));
+    doc_ids.len()
+}
+
+/// Drops a stored document and its postings, collecting the terms it leaves
+/// empty into `emptied` for the caller to remove from the dictionary.
+fn remove_document(segment: &mut Segment, doc_id: i64, emptied: &mut HashSet<String>) -> Result<(), SearchError> {
+    let document = segment.document(doc_id).ok_or(SearchError::DocumentNotFound(doc_id))?;
+    segment.docs.remove(&doc_id);
+    if let Some(texts) = &mut segment.compressed_texts {
+        texts.remove(&doc_id);
+    }
+    let doc_len = segment.doc_lengths.remove(&doc_id).unwrap_or(0);
+    segment.total_docs -= 1;
+
+    // only the terms of the document itself can hold one of its postings
+    let mut terms: HashSet<String> = document_tokens(segment, &document).into_iter().map(|(token, _)| token).collect();
+    if terms.is_empty() && doc_len > 0 {
+        // postings built without stored text, see `Segment::from_triples`: any term may hold one
+        terms = segment.dict.terms().map(|t| t.to_string()).collect();
+    }
+    for term in terms {
+        let Some(posting) = segment.dict.get_mut(&term) else {
+            continue;
+        };
+        if posting.remove(doc_id) && posting.is_empty() {
+            emptied.insert(term);
+        }
+    }
+    Ok(())
+}
+
+/// Posting-level changes applied together by `apply_tx`: every posting of the
+/// `removes` documents is dropped, then the `adds` `(term, doc_id, freq)`
+/// postings are added. Stored documents are left alone.
+#[derive(Clone, Debug, Default)]
+pub struct PostingListTx {
+    pub adds: Vec<(String, i64, i32)>,
+    pub removes: Vec<i64>,
+}
+
+/// Applies a transaction as one step: if any remove or add is invalid, the
+/// segment is put back exactly as it was and the error returned. Documents
+/// must be stored for their postings to be removed or added. Removing walks
+/// the whole dictionary, as the postings need not match the stored text.
+/// Document lengths follow the postings: a removed document drops to 0 and
+/// every add counts towards it.
+pub fn apply_tx(segment: &mut Segment, tx: PostingListTx) -> Result<(), SearchError> {
+    let mut saved_postings: HashMap<String, Option<PostingList>> = HashMap::new();
+    let mut saved_lengths: HashMap<i64, Option<u32>> = HashMap::new();
+    let saved_tokens = segment.total_tokens_indexed;
+    match apply_tx_steps(segment, &tx, &mut saved_postings, &mut saved_lengths) {
+        Ok(()) => {
+            let (kept, emptied): (Vec<String>, Vec<String>) = saved_postings.into_keys()
+                .partition(|term| segment.dict.get(term).is_some_and(|posting| !posting.is_empty()));
+            let emptied: HashSet<String> = emptied.into_iter().collect();
+            segment.dict.retain(|term| !emptied.contains(term));
+            upgrade_frequent_postings(segment, kept);
+            Ok(())
+        }
+        Err(e) => {
+            let mut added_terms = HashSet::new();
+            for (term, posting) in saved_postings {
+                match posting {
+                    Some(posting) => segment.dict.insert(&term, posting),
+                    None => {
+                        added_terms.insert(term);
+                    }
+                }
+            }
+            segment.dict.retain(|term| !added_terms.contains(term));
+            if let Some(trigrams) = &mut segment.trigrams {
+                for term in &added_terms {
+                    trigrams.remove(term);
+                }
+            }
+            for (doc_id, len) in saved_lengths {
+                match len {
+                    Some(len) => segment.doc_lengths.insert(doc_id, len),
+                    None => segment.doc_lengths.remove(&doc_id),
+                };
+            }
+            segment.total_tokens_indexed = saved_tokens;
+            Err(e)
+        }
+    }
+}
+
+fn apply_tx_steps(segment: &mut Segment, tx: &PostingListTx, saved_postings: &mut HashMap<String, Option<PostingList>>,
+                  saved_lengths: &mut HashMap<i64, Option<u32>>) -> Result<(), SearchError> {
+    let removes: HashSet<i64> = tx.removes.iter().copied().collect();
+    if let Some(&missing) = tx.removes.iter().find(|id| !segment.docs.contains_key(id)) {
+        return Err(SearchError::DocumentNotFound(missing));
+    }
+    if !removes.is_empty() {
+        let terms: Vec<String> = segment.dict.terms().map(|t| t.to_string()).collect();
+        for term in terms {
+            let mut posting = segment.dict.get(&term).expect("term listed in dict");
+            let original = posting.clone();
+            let mut changed = false;
+            for &doc_id in &removes {
+                changed |= posting.remove(doc_id);
+            }
+            if changed {
+                saved_postings.entry(term.clone()).or_insert(Some(original));
+                segment.dict.insert(&term, posting);
+            }
+        }
+        for &doc_id in &removes {
+            saved_lengths.entry(doc_id).or_insert(segment.doc_lengths.get(&doc_id).copied());
+            segment.doc_lengths.insert(doc_id, 0);
+        }
+    }
+
+    for (term, doc_id, freq) in &tx.adds {
+        if !segment.docs.contains_key(doc_id) {
+            return Err(SearchError::DocumentNotFound(*doc_id));
+        }
+        if *freq <= 0 {
+            return Err(SearchError::InvalidFrequency { term: term.clone(), doc_id: *doc_id, freq: *freq });
+        }
+        let original = segment.dict.get(term);
+        let current = original.as_ref().and_then(|posting| posting.get(*doc_id)).map_or(0, |node| node.freq);
+        if current.checked_add(*freq).is_none() {
+            return Err(SearchError::InvalidFrequency { term: term.clone(), doc_id: *doc_id, freq: *freq });
+        }
+        if original.is_none() {
+            if let Some(trigrams) = &mut segment.trigrams {
+                trigrams.insert(term);
+            }
+        }
+        saved_postings.entry(term.clone()).or_insert_with(|| original.clone());
+        let mut posting = original.unwrap_or_default();
+        posting.add(*doc_id, *freq);
+        segment.dict.insert(term, posting);
+        saved_lengths.entry(*doc_id).or_insert(segment.doc_lengths.get(doc_id).copied());
+        let doc_len = segment.doc_lengths.entry(*doc_id).or_insert(0);
+        *doc_len = doc_len.saturating_add(*freq as u32);
+        segment.total_tokens_indexed = segment.total_tokens_indexed.saturating_add(*freq as u64);
+    }
+    Ok(())
+}
+
+/// Tokens of the text followed by the field values, the latter as "name:token".
+fn document_tokens(segment: &Segment, document: &Document) -> Vec<(String, u32)> {
+    tokenize_document(segment.tokenizer.as_deref(), document)
+}
+
+/// Positions skipped between two values of a multi-value field, so a phrase
+/// with less slop than this cannot match across values.
+const FIELD_VALUE_POSITION_GAP: u32 = 100;
+
+fn tokenize_document(tokenizer: Option<&IndexTokenizer>, document: &Document) -> Vec<(String, u32)> {
+    let tokenize = |text: &str| match tokenizer {
+        Some(tokenizer) => tokenizer.tokenize_with_positions(text),
+        None => NaiveTokenizer::new().tokenize_with_positions(text),
+    };
+    let mut tokens = tokenize(&document.text);
+    for (name, values) in &document.fields {
+        let mut offset: u32 = 0;
+        for value in values {
+            let mut next = offset;
+            for (token, pos) in tokenize(value) {
+                let pos = offset.saturating_add(pos);
+                next = next.max(pos.saturating_add(1 + FIELD_VALUE_POSITION_GAP));
+                tokens.push((format!("{}:{}", name, token), pos));
+            }
+            offset = next;
+        }
+    }
+    tokens
+}
+
+/// Expected vocabulary of `total` documents given the sample document frequency
+/// of every term seen in `sampled` documents.
+fn extrapolate_term_count(doc_freqs: &HashMap<String, usize>, sampled: usize, total: usize) -> usize {
+    let observed = doc_freqs.len() as f64;
+    let q1 = doc_freqs.values().filter(|&&df| df == 1).count() as f64;
+    let q2 = doc_freqs.values().filter(|&&df| df == 2).count() as f64;
+    let m = sampled as f64;
+    // estimated number of terms the corpus holds but the sample missed
+    let unseen = if q2 > 0.0 {
+        (m - 1.0) / m * q1 * q1 / (2.0 * q2)
+    } else {
+        (m - 1.0) / m * q1 * (q1 - 1.0).max(0.0) / 2.0
+    };
+    if unseen <= 0.0 {
+        return observed as usize;
+    }
+    let remaining = (total - sampled) as f64;
+    let found = unseen * (1.0 - (1.0 - q1 / (m * unseen + q1)).powf(remaining));
+    (observed + found).round() as usize
+}
+
+/// Predicted size of an index, see `estimate_index_size`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexSizeEstimate {
+    pub estimated_term_count: usize,
+    pub estimated_posting_list_bytes: usize,
+    pub estimated_doc_store_bytes: usize,
+}
+
+/// Documents in the sample of `estimate_index_size`: 1% of the corpus, but no
+/// fewer than this; smaller samples see too few repeated terms to tell how
+/// large the vocabulary is.
+const MIN_ESTIMATE_SAMPLE: usize = 50;
+
+/// Estimates the size of the index `index_documents_with_config` would build,
+/// from a random 1% sample tokenized with the config's tokenizer and term filter.
+/// Postings and stored bytes grow linearly with the corpus. The vocabulary is
+/// extrapolated from how many terms the sample saw in one or two documents only
+/// (Chao's estimator for incidence data), since new terms get rarer as the corpus grows.
+pub fn estimate_index_size(documents: &[Document], config: &IndexConfig) -> IndexSizeEstimate {
+    let sample_size = (documents.len() / 100).max(MIN_ESTIMATE_SAMPLE).min(documents.len());
+    if sample_size == 0 {
+        return IndexSizeEstimate { estimated_term_count: 0, estimated_posting_list_bytes: 0, estimated_doc_store_bytes: 0 };
+    }
+    let mut rng = SmallRng::seed_from_u64(documents.len() as u64);
+    let sample = rand::seq::index::sample(&mut rng, documents.len(), sample_size);
+
+    let mut doc_freqs: HashMap<String, usize> = HashMap::new();
+    let mut posting_entries = 0;
+    let mut doc_bytes = 0;
+    for index in sample.iter() {
+        let document = &documents[index];
+        doc_bytes += document_size(document);
+        let unique: HashSet<String> = tokenize_document(config.tokenizer.as_deref(), document).into_iter()
+            .map(|(token, _)| token)
+            .filter(|token| config.term_filter.as_ref().map(|f| f.accept(token)).unwrap_or(true))
+            .collect();
+        posting_entries += unique.len();
+        for term in unique {
+            *doc_freqs.entry(term).or_insert(0) += 1;
+        }
+    }
+
+    let scale = documents.len() as f64 / sample_size as f64;
+    IndexSizeEstimate {
+        estimated_term_count: extrapolate_term_count(&doc_freqs, sample_size, documents.len()),
+        estimated_posting_list_bytes: (posting_entries as f64 * scale) as usize * POSTING_NODE_SIZE,
+        estimated_doc_store_bytes: (doc_bytes as f64 * scale) as usize,
+    }
+}
+
+/// Rough memory use of a segment: the stored documents at their average size
+/// plus one `PostingNode` per posting entry.
+pub fn estimated_memory_bytes(docs: usize, doc_bytes: usize, posting_entries: usize) -> usize {
+    let avg_doc_size = doc_bytes.checked_div(docs).unwrap_or(0);
+    docs * avg_doc_size + posting_entries * POSTING_NODE_SIZE
+}
+
+fn decompress_text(bytes: &[u8]) -> String {
+    let text = lz4_flex::decompress_size_prepended(bytes).expect("compressed by index_document");
+    String::from_utf8(text).expect("compressed from a String")
+}
+
+/// Bytes of text held by a document, fields included.
+fn document_size(document: &Document) -> usize {
+    let fields: usize = document.fields.iter()
+        .map(|(name, values)| name.len() + values.iter().map(|v| v.len()).sum::<usize>())
+        .sum();
+    document.text.len() + fields
+}
+
+/// Indexes one document and returns how many new posting entries it created.
+fn index_document(segment: &mut Segment, document: Document, config: &IndexConfig) -> usize {
+    let doc_id = document.id;
+    let tokens = document_tokens(segment, &document);
+    let mut document = document;
+    if let Some(texts) = &mut segment.compressed_texts {
+        texts.insert(doc_id, lz4_flex::compress_prepend_size(document.text.as_bytes()));
+        document.text = String::new();
+    }
+    let link_to_doc = Arc::new(document);
+
+    segment.docs.insert(doc_id, link_to_doc);
+    segment.total_docs += 1;
+    segment.doc_lengths.insert(doc_id, tokens.len() as u32);
+    segment.total_tokens_indexed += tokens.len() as u64;
+
+    // one posting update per distinct term rather than per token
+    let mut counts: HashMap<&str, i32> = HashMap::new();
+    for x in &tokens {
+        let token = x.0.as_str();
+        if config.term_filter.as_ref().map(|f| !f.accept(token)).unwrap_or(false) {
+            continue;
+        }
+        *counts.entry(token).or_insert(0) += 1;
+    }
+
+    let mut new_entries = 0;
+    for (token, count) in counts {
+        if !segment.dict.contains(token) {
+            if let Some(trigrams) = &mut segment.trigrams {
+                trigrams.insert(token);
+            }
+        }
+        let posting = segment.dict.get_or_insert_default(token);
+        if !posting.contains(doc_id) {
+            new_entries += 1;
+        } else if config.deduplicate_tokens {
+            continue;
+        }
+        posting.add(doc_id, if config.deduplicate_tokens { 1 } else { count });
+    }
+    new_entries
+}
+
+/// Summary counters of a segment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentStats {
+    pub total_docs: usize,
+    pub unique_terms: usize,
+    pub total_tokens_indexed: u64,
+    pub avg_doc_len: f32,
+}
+
+pub fn segment_stats(segment: &Segment) -> SegmentStats {
+    SegmentStats {
+        total_docs: segment.total_docs,
+        unique_terms: segment.dict.len(),
+        total_tokens_indexed: segment.total_tokens_indexed,
+        avg_doc_len: avg_doc_len(segment),
+    }
+}
+
+/// Corpus-wide counters of one term, see `term_stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermStats {
+    /// Documents containing the term.
+    pub doc_freq: usize,
+    /// Occurrences of the term summed over every document.
+    pub total_term_freq: u64,
+}
+
+/// Counters of a term, `None` when it is not indexed.
+pub fn term_stats(segment: &Segment, term: &str) -> Option<TermStats> {
+    let posting = segment.dict.get(term)?;
+    Some(TermStats {
+        doc_freq: posting.len(),
+        total_term_freq: posting.nodes().map(|node| node.freq as u64).sum(),
+    })
+}
+
+/// Occurrences of a term across the whole corpus, 0 when it is not indexed.
+pub fn total_term_freq(segment: &Segment, term: &str) -> u64 {
+    term_stats(segment, term).map_or(0, |stats| stats.total_term_freq)
+}
+
+/// Changes from one version of a segment to the next, see `diff`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SegmentDiff {
+    pub added_docs: Vec<i64>,
+    pub deleted_docs: Vec<i64>,
+    /// Terms added, removed, or whose postings changed.
+    pub modified_terms: Vec<String>,
+}
+
+impl SegmentDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_docs.is_empty() && self.deleted_docs.is_empty() && self.modified_terms.is_empty()
+    }
+}
+
+/// Combines segments into one built segment without re-tokenizing: postings,
+/// stored documents and lengths are copied over. A doc_id found in several
+/// segments keeps the version of the last one, as if it had been re-added with
+/// `add_document`; the merged segment uses that segment's tokenizer too.
+pub fn merge(segments: Vec<Segment>) -> Segment {
+    let mut merged = Segment::new();
+    let mut postings: HashMap<String, PostingList> = HashMap::new();
+    for segment in segments.into_iter().rev() {
+        if merged.docs.is_empty() {
+            merged.tokenizer = segment.tokenizer.clone();
+        }
+        let fresh: HashSet<i64> = segment.docs.keys().filter(|id| !merged.docs.contains_key(id)).copied().collect();
+        for term in segment.dict.terms() {
+            let posting = segment.dict.get(term).expect("term listed in dict");
+            for node in posting.nodes().filter(|node| fresh.contains(&node.doc_id)) {
+                postings.entry(term.to_string()).or_default().add(node.doc_id, node.freq);
+            }
+        }
+        for id in fresh {
+            merged.docs.insert(id, segment.document(id).expect("doc_id taken from segment.docs"));
+            let doc_len = segment.doc_lengths.get(&id).copied().unwrap_or(0);
+            merged.doc_lengths.insert(id, doc_len);
+            merged.total_tokens_indexed += doc_len as u64;
+        }
+    }
+    merged.total_docs = merged.docs.len();
+    let terms: Vec<String> = postings.keys().cloned().collect();
+    for (term, posting) in postings {
+        merged.dict.insert(&term, posting);
+    }
+    upgrade_frequent_postings(&mut merged, terms);
+    merged.built.store(true, AtomicOrdering::Release);
+    merged
+}
+
+/// Compares the stored documents and posting lists of two segments. All lists
+/// are sorted. A replaced document shows up through the terms it touched.
+pub fn diff(old: &Segment, new: &Segment) -> SegmentDiff {
+    let mut added_docs: Vec<i64> = new.docs.keys().filter(|id| !old.docs.contains_key(id)).copied().collect();
+    let mut deleted_docs: Vec<i64> = old.docs.keys().filter(|id| !new.docs.contains_key(id)).copied().collect();
+    added_docs.sort();
+    deleted_docs.sort();
+
+    let nodes = |segment: &Segment, term: &str| -> Vec<(i64, i32)> {
+        segment.dict.get(term).map(|posting| posting.nodes().map(|node| (node.doc_id, node.freq)).collect())
+            .unwrap_or_default()
+    };
+    let terms: BTreeSet<&str> = old.dict.terms().chain(new.dict.terms()).collect();
+    let modified_terms = terms.into_iter()
+        .filter(|term| nodes(old, term) != nodes(new, term))
+        .map(|term| term.to_string())
+        .collect();
+    SegmentDiff { added_docs, deleted_docs, modified_terms }
+}
+
+/// Inconsistency found by `verify_integrity`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IntegrityError {
+    /// A posting list refers to a document that is not stored in the segment.
+    UnknownDocument { term: String, doc_id: i64 },
+    /// A posting records a term that does not occur in the document.
+    ZeroFrequency { term: String, doc_id: i64 },
+    /// The doc_ids of a posting list are not strictly increasing.
+    UnsortedPostings { term: String },
+    /// `total_docs` disagrees with the number of stored documents.
+    TotalDocsMismatch { total_docs: usize, stored: usize },
+}
+
+/// Checks that every posting list is sorted, has positive frequencies and only
+/// refers to stored documents. All problems are reported, not just the first.
+pub fn verify_integrity(segment: &Segment) -> Result<(), Vec<IntegrityError>> {
+    let mut errors = Vec::new();
+    if segment.total_docs != segment.docs.len() {
+        errors.push(IntegrityError::TotalDocsMismatch { total_docs: segment.total_docs, stored: segment.docs.len() });
+    }
+    for term in segment.dict.terms() {
+        let posting = segment.dict.get(term).expect("term listed in dict");
+        let mut previous: Option<i64> = None;
+        let mut sorted = true;
+        for node in posting.nodes() {
+            if !segment.docs.contains_key(&node.doc_id) {
+                errors.push(IntegrityError::UnknownDocument { term: term.to_string(), doc_id: node.doc_id });
+            }
+            if node.freq <= 0 {
+                errors.push(IntegrityError::ZeroFrequency { term: term.to_string(), doc_id: node.doc_id });
+            }
+            if previous.is_some_and(|previous| previous >= node.doc_id) {
+                sorted = false;
+            }
+            previous = Some(node.doc_id);
+        }
+        if !sorted {
+            errors.push(IntegrityError::UnsortedPostings { term: term.to_string() });
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn avg_doc_len(segment: &Segment) -> f32 {
+    if segment.doc_lengths.is_empty() {
+        return 0.0;
+    }
+    let total: u64 = segment.doc_lengths.values().map(|&len| len as u64).sum();
+    total as f32 / segment.doc_lengths.len() as f32
+}
+
+/// Log-base-2 inverse document frequency of a term, 0.0 when it is not indexed.
+pub fn idf(segment: &Segment, term: &str) -> f32 {
+    match segment.dict.get(term) {
+        Some(posting) if !posting.is_empty() => {
+            (segment.total_docs as f32 / posting.len() as f32).log2()
+        }
+        _ => 0.0,
+    }
+}
+
+/// Frequency of a term within one document, 0 when the document does not contain it.
+pub fn term_freq(segment: &Segment, term: &str, doc_id: i64) -> i32 {
+    segment.dict.get(term)
+        .and_then(|posting| posting.get(doc_id).map(|node| node.freq))
+        .unwrap_or(0)
+}
+
+/// Checks whether a term is indexed without cloning its posting list.
+pub fn term_exists(segment: &Segment, term: &str) -> bool {
+    segment.dict.contains(term)
+}
+
+/// Every indexed term in lexicographic order.
+pub fn iter_terms(segment: &Segment) -> impl Iterator<Item = &str> {
+    segment.dict.terms()
+}
+
+/// Removes every term starting with `prefix` from the dictionary.
+pub fn delete_by_prefix(segment: &mut Segment, prefix: &str) {
+    segment.dict.retain(|term| !term.starts_with(prefix));
+}
+
+/// Writes the inverted index as tab-separated `term`, `doc_id`, `freq` lines,
+/// terms in lexicographic order and doc_ids ascending within a term.
+pub fn dump_tsv(segment: &Segment, writer: &mut impl Write) -> std::io::Result<()> {
+    for term in segment.dict.terms() {
+        let posting = segment.dict.get(term).expect("term listed in dict");
+        for node in posting.nodes() {
+            writeln!(writer, "{}\t{}\t{}", term, node.doc_id, node.freq)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds a segment from the output of `dump_tsv`. The dump holds no document
+/// text, so documents come back empty, as with `Segment::from_triples`, and
+/// deleting one walks the whole dictionary.
+pub fn load_tsv(reader: impl BufRead) -> std::io::Result<Segment> {
+    let mut triples = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        triples.push(parse_tsv_line(&line).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, format!("malformed TSV line {}: {:?}", number + 1, line)))?);
+    }
+    Segment::from_triples(triples.into_iter()).map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn parse_tsv_line(line: &str) -> Option<(String, i64, i32)> {
+    let mut columns = line.split('\t');
+    let term = columns.next()?;
+    let doc_id = columns.next()?.parse().ok()?;
+    let freq = columns.next()?.parse().ok()?;
+    if columns.next().is_some() || term.is_empty() {
+        return None;
+    }
+    Some((term.to_string(), doc_id, freq))
+}
+
+/// JSON form of a segment used by `export_gz`.
+#[derive(Serialize, Deserialize)]
+struct SegmentSnapshot {
+    documents: Vec<Document>,
+    doc_lengths: BTreeMap<i64, u32>,
+    total_tokens_indexed: u64,
+    /// Term to its `(doc_id, freq)` postings.
+    postings: BTreeMap<String, Vec<(i64, i32)>>,
+}
+
+/// Writes the segment as gzip-compressed JSON, for archiving.
+pub fn export_gz(segment: &Segment, path: &str) -> std::io::Result<()> {
+    let mut documents: Vec<Document> = segment.docs.keys()
+        .map(|&doc_id| segment.document(doc_id).expect("doc_id taken from segment.docs").as_ref().clone())
+        .collect();
+    documents.sort_by_key(|doc| doc.id);
+    let snapshot = SegmentSnapshot {
+        documents,
+        doc_lengths: segment.doc_lengths.iter().map(|(&id, &len)| (id, len)).collect(),
+        total_tokens_indexed: segment.total_tokens_indexed,
+        postings: segment.dict.terms()
+            .map(|term| {
+                let posting = segment.dict.get(term).expect("term listed in dict");
+                (term.to_string(), posting.nodes().map(|node| (node.doc_id, node.freq)).collect())
+            })
+            .collect(),
+    };
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    serde_json::to_writer(&mut encoder, &snapshot)?;
+    encoder.finish()?.flush()
+}
+
+/// Reads a segment written by `export_gz`.
+pub fn import_gz(path: &str) -> std::io::Result<Segment> {
+    let decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+    let snapshot: SegmentSnapshot = serde_json::from_reader(decoder)?;
+    let mut segment = Segment::new();
+    for (term, nodes) in snapshot.postings {
+        segment.dict.insert(&term, stored_postings(nodes.into_iter())?);
+    }
+    for document in snapshot.documents {
+        segment.docs.insert(document.id, Arc::new(document));
+    }
+    segment.doc_lengths = snapshot.doc_lengths.into_iter().collect();
+    segment.total_docs = segment.docs.len();
+    segment.total_tokens_indexed = snapshot.total_tokens_indexed;
+    let terms: Vec<String> = segment.dict.terms().map(|t| t.to_string()).collect();
+    upgrade_frequent_postings(&mut segment, terms);
+    segment.built.store(true, AtomicOrdering::Release);
+    Ok(segment)
+}
+
+/// Writes the segment into `path` as three files: the term dictionary (term plus
+/// offset and length of its posting list), the posting lists and the stored documents.
+/// All integers are little-endian and every file starts with `VERSION`.
+#[tracing::instrument(skip_all, fields(path = path, total_docs = segment.total_docs, elapsed_ms = Empty))]
+pub fn flush_to_disk(segment: &Segment, path: &str) -> Result<(), SearchError> {
+    let started = Instant::now();
+    fs::create_dir_all(path)?;
+    let mut term_bw = BufWriter::new(File::create(format!("{}/{}", path, TERM_DICT_FILE_NAME))?);
+    let mut posting_bw = BufWriter::new(File::create(format!("{}/{}", path, POSTING_LISTS_FILE_NAME))?);
+    let mut index_bw = BufWriter::new(File::create(format!("{}/{}", path, POSTING_INDEX_FILE_NAME))?);
+    let mut docs_bw = BufWriter::new(File::create(format!("{}/{}", path, STORED_DOCS_FILE_NAME))?);
+
+    encode_segment(segment, &mut term_bw, &mut posting_bw, &mut index_bw, &mut docs_bw)?;
+
+    term_bw.flush()?;
+    posting_bw.flush()?;
+    index_bw.flush()?;
+    docs_bw.flush()?;
+    debug!("flushed segment to {}: terms {} bytes, postings {} bytes, docs {} bytes in {:?}",
+           path, term_bw.get_ref().metadata()?.len(), posting_bw.get_ref().metadata()?.len(),
+           docs_bw.get_ref().metadata()?.len(), started.elapsed());
+    tracing::Span::current().record("elapsed_ms", started.elapsed().as_millis() as u64);
+    Ok(())
+}
+
+/// Serializes the four files written by `flush_to_disk`.
+fn encode_segment(segment: &Segment, term_bw: &mut impl Write, posting_bw: &mut impl Write,
+                  index_bw: &mut impl Write, docs_bw: &mut impl Write) -> std::io::Result<()> {
+    term_bw.write_all(&VERSION.to_le_bytes())?;
+    posting_bw.write_all(&VERSION.to_le_bytes())?;
+    docs_bw.write_all(&VERSION.to_le_bytes())?;
+
+    term_bw.write_all(&(segment.dict.len() as u32).to_le_bytes())?;
+    let mut offset: u64 = 0;
+    let mut index_entries = Vec::with_capacity(segment.dict.len());
+    for term in segment.dict.terms() {
+        let posting = segment.dict.get(term).expect("term listed in dict");
+        write_string(term_bw, term)?;
+        term_bw.write_all(&offset.to_le_bytes())?;
+        term_bw.write_all(&(posting.len() as u32).to_le_bytes())?;
+        for node in posting.nodes() {
+            posting_bw.write_all(&node.doc_id.to_le_bytes())?;
+            posting_bw.write_all(&node.freq.to_le_bytes())?;
+        }
+        index_entries.push((term_hash(term), offset, posting.len() as u32));
+        offset += (posting.len() * POSTING_NODE_SIZE) as u64;
+    }
+
+    index_entries.sort();
+    index_bw.write_all(&VERSION.to_le_bytes())?;
+    index_bw.write_all(&(index_entries.len() as u32).to_le_bytes())?;
+    for (hash, offset, len) in index_entries {
+        index_bw.write_all(&hash.to_le_bytes())?;
+        index_bw.write_all(&offset.to_le_bytes())?;
+        index_bw.write_all(&len.to_le_bytes())?;
+    }
+
+    docs_bw.write_all(&(segment.docs.len() as u32).to_le_bytes())?;
+    for doc_id in segment.docs.keys() {
+        let doc = segment.document(*doc_id).expect("doc_id taken from segment.docs");
+        let doc_len = segment.doc_lengths.get(doc_id).copied().unwrap_or(0);
+        docs_bw.write_all(&doc_id.to_le_bytes())?;
+        docs_bw.write_all(&doc_len.to_le_bytes())?;
+        write_string(docs_bw, &doc.text)?;
+        docs_bw.write_all(&(doc.fields.len() as u32).to_le_bytes())?;
+        for (name, values) in &doc.fields {
+            write_string(docs_bw, name)?;
+            docs_bw.write_all(&(values.len() as u32).to_le_bytes())?;
+            for value in values {
+                write_string(docs_bw, value)?;
+            }
+        }
+        match &doc.source {
+            Some(source) => {
+                docs_bw.write_all(&[1])?;
+                write_string(docs_bw, source)?;
+            }
+            None => docs_bw.write_all(&[0])?,
+        }
+    }
+    Ok(())
+}
+
+fn decode_postings(bytes: &[u8]) -> std::io::Result<PostingList> {
+    stored_postings(bytes.chunks_exact(POSTING_NODE_SIZE).map(|chunk| {
+        (i64::from_le_bytes(chunk[0..8].try_into().unwrap()), i32::from_le_bytes(chunk[8..12].try_into().unwrap()))
+    }))
+}
+
+/// Posting list from `(doc_id, freq)` nodes read back from a file. They must
+/// come as `PostingList::nodes` writes them: doc_ids strictly increasing and
+/// every freq positive; anything else is rejected as corrupt.
+fn stored_postings(nodes: impl Iterator<Item = (i64, i32)>) -> std::io::Result<PostingList> {
+    let mut posting = BTreeMap::new();
+    let mut last = None;
+    for (doc_id, freq) in nodes {
+        if freq <= 0 {
+            return Err(invalid_data("posting with a non-positive frequency"));
+        }
+        if last.is_some_and(|last| doc_id <= last) {
+            return Err(invalid_data("posting doc_ids not strictly increasing"));
+        }
+        last = Some(doc_id);
+        posting.insert(doc_id, PostingNode { doc_id, freq });
+    }
+    Ok(PostingList { repr: PostingListRepr::Sparse(posting) })
+}
+
+/// Reads the stored documents and assembles a built segment around `dict`.
+fn segment_from_parts(dict: TermDict, docs_br: &mut impl Read) -> Result<Segment, SearchError> {
+    let mut docs = HashMap::new();
+    let mut doc_lengths = HashMap::new();
+    let doc_count = read_u32(docs_br)?;
+    for _ in 0..doc_count {
+        let id = read_i64(docs_br)?;
+        let doc_len = read_u32(docs_br)?;
+        let text = read_string(docs_br)?;
+        let mut fields = HashMap::new();
+        let field_count = read_u32(docs_br)?;
+        for _ in 0..field_count {
+            let name = read_string(docs_br)?;
+            let value_count = read_u32(docs_br)?;
+            let mut values = Vec::new();
+            for _ in 0..value_count {
+                values.push(read_string(docs_br)?);
+            }
+            fields.insert(name, values);
+        }
+        let mut has_source = [0u8];
+        docs_br.read_exact(&mut has_source)?;
+        let source = if has_source[0] == 1 { Some(read_string(docs_br)?) } else { None };
+        docs.insert(id, Arc::new(Document { id, text, fields, source }));
+        doc_lengths.insert(id, doc_len);
+    }
+
+    let total_docs = docs.len();
+    let total_tokens_indexed = doc_lengths.values().map(|&len| len as u64).sum();
+    let mut segment = Segment {
+        dict, docs, doc_lengths, total_docs, total_tokens_indexed,
+        created_at: Instant::now(),
+        built: AtomicBool::new(true),
+        tokenizer: None,
+        compressed_texts: None,
+        trigrams: None,
+    };
+    let terms: Vec<String> = segment.dict.terms().map(|t| t.to_string()).collect();
+    upgrade_frequent_postings(&mut segment, terms);
+    Ok(segment)
+}
+
+/// Loads only the posting lists of `terms`, located through the sidecar index
+/// written by `flush_to_disk`, so the posting file is never read as a whole.
+/// Documents are always loaded. Indexes without a sidecar are loaded in full.
+pub fn load_terms_from_disk(path: &str, terms: &[&str]) -> Result<Segment, SearchError> {
+    let idx_path = format!("{}/{}", path, POSTING_INDEX_FILE_NAME);
+    if !std::path::Path::new(&idx_path).exists() {
+        return load_from_disk(path);
+    }
+    let mut idx_br = BufReader::new(File::open(idx_path)?);
+    let mut posting_file = File::open(format!("{}/{}", path, POSTING_LISTS_FILE_NAME))?;
+    let mut docs_br = BufReader::new(File::open(format!("{}/{}", path, STORED_DOCS_FILE_NAME))?);
+    check_version(&mut idx_br)?;
+    check_version(&mut posting_file)?;
+    check_version(&mut docs_br)?;
+    let posting_bytes = posting_file.metadata()?.len().saturating_sub(4);
+
+    let entry_count = read_u32(&mut idx_br)?;
+    let mut entries = Vec::new();
+    for _ in 0..entry_count {
+        entries.push((read_u64(&mut idx_br)?, read_u64(&mut idx_br)?, read_u32(&mut idx_br)?));
+    }
+
+    // only needed to tell apart terms whose hashes collide
+    let mut term_offsets: Option<HashMap<String, (u64, u32)>> = None;
+    let mut dict = TermDict::new();
+    for &term in terms {
+        if dict.contains(term) {
+            continue;
+        }
+        let hash = term_hash(term);
+        let start = entries.partition_point(|&(h, _, _)| h < hash);
+        let end = entries.partition_point(|&(h, _, _)| h <= hash);
+        let (offset, len) = match end - start {
+            0 => continue,
+            1 => (entries[start].1, entries[start].2),
+            _ => {
+                if term_offsets.is_none() {
+                    term_offsets = Some(read_term_offsets(path)?);
+                }
+                match term_offsets.as_ref().and_then(|offsets| offsets.get(term)) {
+                    Some(&location) => location,
+                    None => continue,
+                }
+            }
+        };
+        let size = len as u64 * POSTING_NODE_SIZE as u64;
+        if offset.checked_add(size).is_none_or(|end| end > posting_bytes) {
+            return Err(invalid_data("posting list out of bounds").into());
+        }
+        let mut bytes = vec![0u8; size as usize];
+        posting_file.seek(SeekFrom::Start(4 + offset))?;
+        posting_file.read_exact(&mut bytes)?;
+        dict.insert(term, decode_postings(&bytes)?);
+    }
+    segment_from_parts(dict, &mut docs_br)
+}
+
+/// Offset and node count of every term in the terms dict file.
+fn read_term_offsets(path: &str) -> Result<HashMap<String, (u64, u32)>, SearchError> {
+    let mut term_br = BufReader::new(File::open(format!("{}/{}", path, TERM_DICT_FILE_NAME))?);
+    check_version(&mut term_br)?;
+    let mut offsets = HashMap::new();
+    for _ in 0..read_u32(&mut term_br)? {
+        let term = read_string(&mut term_br)?;
+        let offset = read_u64(&mut term_br)?;
+        offsets.insert(term, (offset, read_u32(&mut term_br)?));
+    }
+    Ok(offsets)
+}
+
+/// 64-bit FNV-1a, stable across builds so it can be stored on disk.
+fn term_hash(term: &str) -> u64 {
+    term.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Reads a segment previously written by `flush_to_disk`.
+#[tracing::instrument(fields(total_docs = Empty, elapsed_ms = Empty))]
+pub fn load_from_disk(path: &str) -> Result<Segment, SearchError> {
+    let started = Instant::now();
+    let mut term_br = BufReader::new(File::open(format!("{}/{}", path, TERM_DICT_FILE_NAME))?);
+    let mut posting_br = BufReader::new(File::open(format!("{}/{}", path, POSTING_LISTS_FILE_NAME))?);
+    let mut docs_br = BufReader::new(File::open(format!("{}/{}", path, STORED_DOCS_FILE_NAME))?);
+
+    let mut postings = Vec::new();
+    check_version(&mut posting_br)?;
+    posting_br.read_to_end(&mut postings)?;
+
+    let segment = decode_segment(&mut term_br, &postings, &mut docs_br)?;
+    debug!("loaded segment from {}: terms {} bytes, postings {} bytes, docs {} bytes in {:?}",
+           path, term_br.get_ref().metadata()?.len(), posting_br.get_ref().metadata()?.len(),
+           docs_br.get_ref().metadata()?.len(), started.elapsed());
+    tracing::Span::current()
+        .record("total_docs", segment.total_docs)
+        .record("elapsed_ms", started.elapsed().as_millis() as u64);
+    Ok(segment)
+}
+
+/// Parses the files read by `load_from_disk`; `postings` is the posting file
+/// without its version header.
+fn decode_segment(term_br: &mut impl Read, postings: &[u8], docs_br: &mut impl Read) -> Result<Segment, SearchError> {
+    check_version(term_br)?;
+    check_version(docs_br)?;
+    let mut dict = TermDict::new();
+    let term_count = read_u32(term_br)?;
+    for _ in 0..term_count {
+        let term = read_string(term_br)?;
+        let offset = read_u64(term_br)? as usize;
+        let len = read_u32(term_br)? as usize;
+        let bytes = offset.checked_add(len * POSTING_NODE_SIZE)
+            .and_then(|end| postings.get(offset..end))
+            .ok_or_else(|| invalid_data("posting list out of bounds"))?;
+        dict.insert(&term, decode_postings(bytes)?);
+    }
+    segment_from_parts(dict, docs_br)
+}
+
+const POSTING_NODE_SIZE: usize = 12;
+
+fn check_version(reader: &mut impl Read) -> Result<(), SearchError> {
+    let found = read_u32(reader)?;
+    if found != VERSION {
+        return Err(SearchError::IncompatibleVersion { found, expected: VERSION });
+    }
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_i64(reader: &mut impl Read) -> std::io::Result<i64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> std::io::Result<()> {
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> std::io::Result<String> {
+    let len = read_u32(reader)? as usize;
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+    }
+    String::from_utf8(buf).map_err(|_| invalid_data("string is not valid utf-8"))
+}
+
+/// Tf-idf of an indexed term within one document, `None` if the document does
+/// not contain it.
+fn doc_term_score(segment: &Segment, term: &str, doc_id: i64) -> Option<f32> {
+    let posting = segment.dict.get(term)?;
+    let node = posting.get(doc_id)?;
+    Some(score_tf_idf(node.freq, posting.len() as i32, segment.total_docs as i32))
+}
+
+fn score_tf_idf(term_freq: i32, total_docs_with_term: i32, total_docs_in_segment: i32) -> f32 {
+    if total_docs_with_term == 0 {
+        0 as f32
+    } else {
+        let base: f32 = total_docs_in_segment as f32 / total_docs_with_term as f32;
+        term_freq as f32 * base.log2()
+    }
+}
+
+/// Scores one query term within one document.
+pub trait DocumentScorer {
+    fn score(&self, term_freq: i32, doc_freq: i32, total_docs: usize, doc_len: u32, avg_doc_len: f32) -> f32;
+}
+
+/// The default scorer: `term_freq * log2(total_docs / doc_freq)`.
+pub struct TfIdfScorer;
+
+impl DocumentScorer for TfIdfScorer {
+    fn score(&self, term_freq: i32, doc_freq: i32, total_docs: usize, _doc_len: u32, _avg_doc_len: f32) -> f32 {
+        score_tf_idf(term_freq, doc_freq, total_docs as i32)
+    }
+}
+
+/// Aggregates the per-term scores of a document.
+pub trait ScoreCombiner {
+    fn combine(&self, per_term_scores: &[f32]) -> f32;
+}
+
+pub struct SumCombiner;
+
+impl ScoreCombiner for SumCombiner {
+    fn combine(&self, per_term_scores: &[f32]) -> f32 {
+        per_term_scores.iter().sum()
+    }
+}
+
+pub struct MaxCombiner;
+
+impl ScoreCombiner for MaxCombiner {
+    fn combine(&self, per_term_scores: &[f32]) -> f32 {
+        per_term_scores.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+pub struct AvgCombiner;
+
+impl ScoreCombiner for AvgCombiner {
+    fn combine(&self, per_term_scores: &[f32]) -> f32 {
+        if per_term_scores.is_empty() {
+            return 0.0;
+        }
+        per_term_scores.iter().sum::<f32>() / per_term_scores.len() as f32
+    }
+}
+
+/// Cursor over one posting list. `doc_id` and `freq` describe the current node;
+/// once the last node has been consumed `exhausted` is set.
+struct Iter<'a> {
+    doc_id: i64,
+    freq: i32,
+    /// Document frequency of the term, taken once when the cursor is created.
+    /// A posting list holds exactly one node per document containing the term,
+    /// so its length is the df; the scoring loop reads this field instead of
+    /// going back to the list for every candidate. Field terms scored without
+    /// `per_field_idf` carry the df of their value across the whole index.
+    doc_freq: usize,
+    exhausted: bool,
+    rest: PostingIter<'a>,
+}
+
+impl<'a> Iter<'a> {
+    #[cfg(any(test, feature = "testing"))]
+    fn new(posting: &'a PostingList) -> Option<Iter<'a>> {
+        Iter::from_nodes(posting.nodes(), posting.len())
+    }
+
+    fn from_nodes(mut rest: PostingIter<'a>, doc_freq: usize) -> Option<Iter<'a>> {
+        let first = rest.next()?;
+        Some(Iter { doc_id: first.doc_id, freq: first.freq, doc_freq, exhausted: false, rest })
+    }
+
+    /// Moves to the next node, returning false if there is none.
+    fn advance(&mut self) -> bool {
+        match self.rest.next() {
+            Some(node) => {
+                self.doc_id = node.doc_id;
+                self.freq = node.freq;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub const DEFAULT_MAX_QUERY_TERMS: usize = 16;
+
+/// Query-time options for `search_with_config`.
+pub struct SearchConfig {
+    /// Divide every score by the top score so results fall within [0.0, 1.0].
+    pub normalize_scores: bool,
+    /// Scores a single query term within a document. Defaults to `TfIdfScorer`.
+    pub scorer: Box<dyn DocumentScorer>,
+    /// Folds the per-term scores of a document into one. Defaults to `SumCombiner`.
+    pub combiner: Box<dyn ScoreCombiner>,
+    /// Short field names usable in queries, e.g. "q" -> "question" turns `q:rust`
+    /// into `question:rust`.
+    pub field_aliases: HashMap<String, String>,
+    /// Caps how many hits each segment of a `MultiSegmentIndex` may contribute.
+    pub max_hits_per_segment: Option<usize>,
+    /// Queries with more terms fail with `TooManyQueryTerms`, so one request
+    /// cannot open an unbounded number of posting iterators.
+    pub max_query_terms: usize,
+    /// Score a field term such as `tag:rust` with the document frequency of its
+    /// own posting list. When false the df counts every document holding the
+    /// value anywhere, body or any field, so a value that is rare within its
+    /// field but common elsewhere weighs less. Defaults to true.
+    pub per_field_idf: bool,
+    /// Only return documents whose `Document::source` equals this value.
+    pub source_filter: Option<String>,
+    /// Drop documents scoring below this before they enter the top-k, e.g. to
+    /// only fetch what can beat results already held. Compared with the raw
+    /// score, before `normalize_scores`.
+    pub min_score: Option<f32>,
+    /// Cap every term's share of a document score, so one rare term cannot
+    /// dominate it. See `cap_term_scores`; documents matching a single term
+    /// are left alone.
+    pub max_term_score_fraction: Option<f32>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            normalize_scores: false,
+            scorer: Box::new(TfIdfScorer),
+            combiner: Box::new(SumCombiner),
+            field_aliases: HashMap::new(),
+            max_hits_per_segment: None,
+            max_query_terms: DEFAULT_MAX_QUERY_TERMS,
+            per_field_idf: true,
+            source_filter: None,
+            min_score: None,
+            max_term_score_fraction: None,
+        }
+    }
+}
+
+fn resolve_field_alias(token: &str, aliases: &HashMap<String, String>) -> Option<String> {
+    let (field, value) = token.split_once(':')?;
+    aliases.get(field).map(|name| format!("{}:{}", name, value))
+}
+
+pub fn search(segment: &Segment, query: String, size: i32) -> Result<Vec<TopKDoc>, SearchError> {
+    search_with_config(segment, query, size, &SearchConfig::default())
+}
+
+#[tracing::instrument(skip_all, fields(query = %query, total_docs = segment.total_docs,
+                                         result_count = Empty, elapsed_ms = Empty))]
+pub fn search_with_config(segment: &Segment, query: String, size: i32,
+                          config: &SearchConfig) -> Result<Vec<TopKDoc>, SearchError> {
+    let started = Instant::now();
+    let postings = query_postings(segment, query, config)?;
+    let mut result = score_postings(segment, postings, size, config, &|_| true);
+    if config.normalize_scores {
+        normalize_scores(&mut result);
+    }
+    tracing::Span::current()
+        .record("result_count", result.len())
+        .record("elapsed_ms", started.elapsed().as_millis() as u64);
+    #[cfg(feature = "metrics")]
+    metrics::record_search(started.elapsed());
+    Ok(result)
+}
+
+/// Like `search`, but skips documents for which `acl_fn` returns false. The check
+/// runs on every match before it enters the top-k, so hidden documents never take
+/// up result slots.
+pub fn search_with_acl(segment: &Segment, query: &str, size: i32, acl_fn: impl Fn(i64) -> bool) -> Vec<TopKDoc> {
+    let config = SearchConfig::default();
+    match query_postings(segment, query.to_string(), &config) {
+        Ok(postings) => score_postings(segment, postings, size, &config, &acl_fn),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Tokenizes the query and looks up the posting list of every term, resolving
+/// field aliases, along with the document frequency to score it with. Terms
+/// that are not indexed are left out.
+fn query_postings(segment: &Segment, query: String,
+                  config: &SearchConfig) -> Result<Vec<(PostingList, usize)>, SearchError> {
+    query_postings_with(segment, query, config, &mut |term| segment.dict.get(term))
+}
+
+/// `query_postings` with the posting lists fetched through `lookup`, so
+/// `batch_search` can share them between queries.
+fn query_postings_with(segment: &Segment, query: String, config: &SearchConfig,
+                       lookup: &mut dyn FnMut(&str) -> Option<PostingList>)
+                       -> Result<Vec<(PostingList, usize)>, SearchError> {
+    ensure_built(segment)?;
+    let terms = query_terms(&query, segment.tokenizer.as_deref())?;
+    check_query_terms(&terms, config)?;
+    let postings: Vec<(PostingList, usize)> = terms.iter()
+        .map(|term| resolve_field_alias(term, &config.field_aliases).unwrap_or_else(|| term.clone()))
+        .filter_map(|term| {
+            let posting = lookup(&term)?;
+            let doc_freq = match term.split_once(':') {
+                Some((_, value)) if !config.per_field_idf => {
+                    global_doc_freq(value, segment.dict.fields(), |t| {
+                        segment.dict.get(t).map(|posting| posting.doc_ids().collect()).unwrap_or_default()
+                    })
+                }
+                _ => posting.len(),
+            };
+            Some((posting, doc_freq))
+        })
+        .collect();
+    debug!("query {:?} tokenized into {:?}, {} found in the dictionary", query, terms, postings.len());
+    Ok(postings)
+}
+
+/// Number of documents holding `value` as a body term or in any field, found
+/// by looking up `value` and `field:value` for each of the indexed `fields`.
+/// `doc_ids` yields nothing for a term that is not indexed.
+fn global_doc_freq<'a>(value: &str, fields: impl Iterator<Item = &'a str>,
+                       doc_ids: impl Fn(&str) -> Vec<i64>) -> usize {
+    let mut docs: HashSet<i64> = doc_ids(value).into_iter().collect();
+    for field in fields {
+        docs.extend(doc_ids(&format!("{}:{}", field, value)));
+    }
+    docs.len()
+}
+
+fn check_query_terms(terms: &[String], config: &SearchConfig) -> Result<(), SearchError> {
+    if terms.len() > config.max_query_terms {
+        return Err(SearchError::TooManyQueryTerms { count: terms.len(), max: config.max_query_terms });
+    }
+    Ok(())
+}
+
+fn ensure_built(segment: &Segment) -> Result<(), SearchError> {
+    if !segment.is_built() {
+        return Err(SearchError::IndexNotReady);
+    }
+    Ok(())
+}
+
+/// Splits a query into the terms looked up in the dictionary.
+pub fn parse_query(query: &str) -> Result<Vec<String>, SearchError> {
+    let tokens = NaiveTokenizer::new().tokenize_with_positions(query);
+    if tokens.is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Splits a query with `parse_query` and runs every term through `tokenizer`,
+/// the one the documents were indexed with, so both sides normalize alike. As
+/// at index time, only the value of a `field:value` term is tokenized.
+fn query_terms(query: &str, tokenizer: Option<&IndexTokenizer>) -> Result<Vec<String>, SearchError> {
+    let terms = parse_query(query)?;
+    let Some(tokenizer) = tokenizer else {
+        return Ok(terms);
+    };
+    let mut tokens = Vec::with_capacity(terms.len());
+    for term in terms {
+        match term.split_once(':') {
+            Some((field, value)) => tokens.extend(tokenizer.query_tokens(value).into_iter()
+                .map(|token| format!("{}:{}", field, token))),
+            None => tokens.extend(tokenizer.query_tokens(&term)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Draws `k` of the documents matching `query` without replacement. The same
+/// seed always yields the same sample.
+pub fn search_random_sample(segment: &Segment, query: &str, k: usize, seed: u64) -> Vec<TopKDoc> {
+    let mut matches = match search(segment, query.to_string(), i32::MAX) {
+        Ok(matches) => matches,
+        Err(_) => return Vec::new(),
+    };
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let (sample, _) = matches.partial_shuffle(&mut rng, k);
+    sample.to_vec()
+}
+
+/// Re-ranks retrieved documents with a linear model over named features.
+pub struct LinearReranker {
+    pub weights: HashMap<String, f32>,
+}
+
+impl LinearReranker {
+    /// Replaces each score with dot(weights, features(doc_id)) and re-sorts;
+    /// features without a weight are ignored. Ties keep their retrieval order.
+    pub fn rerank(self, results: Vec<TopKDoc>, features: &dyn Fn(i64) -> HashMap<String, f32>) -> Vec<TopKDoc> {
+        let mut reranked: Vec<TopKDoc> = results.into_iter()
+            .map(|doc| {
+                let score = features(doc.id).iter()
+                    .map(|(name, value)| self.weights.get(name).copied().unwrap_or(0.0) * value)
+                    .sum();
+                TopKDoc { id: doc.id, score: F32(score) }
+            })
+            .collect();
+        // by score alone (F32 orders high to low); the stable sort keeps ties in retrieval order
+        reranked.sort_by_key(|doc| doc.score);
+        reranked
+    }
+}
+
+/// Min-max normalisation: divides each score by the highest one, so the top
+/// document gets 1.0. Left untouched when every score is zero.
+pub fn normalize_scores(results: &mut [TopKDoc]) {
+    let max = results.iter().map(|doc| doc.score.0).fold(0.0, f32::max);
+    if max <= 0.0 {
+        return;
+    }
+    for doc in results.iter_mut() {
+        doc.score = F32(doc.score.0 / max);
+    }
+}
+
+/// Groups hits by the first value of `group_field` in their stored document,
+/// e.g. by brand. Groups come in order of their best hit and keep their hits
+/// best first. Hits whose document lacks the field are left out.
+pub fn group_results(segment: &Segment, results: Vec<TopKDoc>, group_field: &str) -> IndexMap<String, Vec<TopKDoc>> {
+    let mut results = results;
+    results.sort_by(|a, b| b.cmp(a));
+    let mut groups: IndexMap<String, Vec<TopKDoc>> = IndexMap::new();
+    for doc in results {
+        let value = segment.docs.get(&doc.id)
+            .and_then(|document| document.fields.get(group_field))
+            .and_then(|values| values.first());
+        if let Some(value) = value {
+            groups.entry(value.clone()).or_default().push(doc);
+        }
+    }
+    groups
+}
+
+/// Runs every query against the segment. Posting lists are looked up once per
+/// distinct term and shared between the queries; an empty query yields no results.
+pub fn batch_search(segment: &Segment, queries: Vec<String>, size: i32) -> Vec<Vec<TopKDoc>> {
+    batch_search_with_config(segment, queries, size, &SearchConfig::default())
+}
+
+/// Like `batch_search`, with every query parsed, checked and scored exactly as
+/// by `search_with_config`; a query it would reject yields no results.
+pub fn batch_search_with_config(segment: &Segment, queries: Vec<String>, size: i32,
+                                config: &SearchConfig) -> Vec<Vec<TopKDoc>> {
+    let mut lookups: HashMap<String, Option<PostingList>> = HashMap::new();
+    let mut lookup = |term: &str| {
+        #[cfg(feature = "metrics")]
+        metrics::record_cache_lookup(lookups.contains_key(term));
+        if !lookups.contains_key(term) {
+            lookups.insert(term.to_string(), segment.dict.get(term));
+        }
+        lookups[term].clone()
+    };
+    queries.into_iter()
+        .map(|query| match query_postings_with(segment, query, config, &mut lookup) {
+            Ok(postings) => {
+                let mut result = score_postings(segment, postings, size, config, &|_| true);
+                if config.normalize_scores {
+                    normalize_scores(&mut result);
+                }
+                result
+            }
+            Err(_) => Vec::new(),
+        })
+        .collect()
+}
+
+/// Searches for documents containing any term within `max_edits` edits of `term`.
+/// Candidates whose Levenshtein similarity (1 - edit_distance / max_len) is below
+/// `min_similarity` are skipped. Scores of all matched terms are summed per document.
+pub fn search_fuzzy(segment: &Segment, term: &str, max_edits: usize, min_similarity: f32,
+                    size: i32) -> Result<Vec<TopKDoc>, SearchError> {
+    ensure_built(segment)?;
+    if term.trim().is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+
+    // the trigram index only finds every candidate within one edit
+    let candidates: Vec<String> = match &segment.trigrams {
+        Some(trigrams) if max_edits <= 1 && term.chars().count() >= 2 => find_candidates(trigrams, term),
+        _ => segment.dict.terms().map(|t| t.to_string()).collect(),
+    };
+    let mut scores: HashMap<i64, f32> = HashMap::new();
+    for candidate in &candidates {
+        let distance = edit_distance(term, candidate);
+        if distance > max_edits || similarity(term, candidate, distance) < min_similarity {
+            continue;
+        }
+        let Some(posting) = segment.dict.get(candidate) else {
+            continue;
+        };
+        let doc_freq = posting.len() as i32;
+        for node in posting.nodes() {
+            let term_score = score_tf_idf(node.freq, doc_freq, segment.total_docs as i32);
+            *scores.entry(node.doc_id).or_insert(0.0) += term_score;
+        }
+    }
+
+    let mut top_k: BinaryHeap<TopKDoc> = scores.into_iter()
+        .map(|(id, score)| TopKDoc { id, score: F32(score) })
+        .collect();
+    let mut result = Vec::new();
+    while let Some(doc) = top_k.pop() {
+        if result.len() == size as usize {
+            break;
+        }
+        result.push(doc);
+    }
+    Ok(result)
+}
+
+/// Unions posting lists with a k-way merge over a heap, summing the frequencies
+/// of nodes that share a doc_id.
+pub fn merge_posting_lists_or(lists: Vec<&PostingList>) -> PostingList {
+    MergeIter::new(lists).collect()
+}
+
+/// Lazy form of `merge_posting_lists_or`: yields the union in doc_id order
+/// without building a new list. Nodes are yielded by value because dense lists
+/// do not store them.
+pub struct MergeIter<'a> {
+    cursors: Vec<PostingIter<'a>>,
+    heap: BinaryHeap<Reverse<(i64, i32, usize)>>,
+}
+
+impl<'a> MergeIter<'a> {
+    pub fn new(lists: Vec<&'a PostingList>) -> Self {
+        let mut cursors: Vec<PostingIter<'a>> = lists.into_iter().map(|posting| posting.nodes()).collect();
+        let mut heap = BinaryHeap::with_capacity(cursors.len());
+        for (i, cursor) in cursors.iter_mut().enumerate() {
+            if let Some(node) = cursor.next() {
+                heap.push(Reverse((node.doc_id, node.freq, i)));
+            }
+        }
+        MergeIter { cursors, heap }
+    }
+
+    fn refill(&mut self, i: usize) {
+        if let Some(next) = self.cursors[i].next() {
+            self.heap.push(Reverse((next.doc_id, next.freq, i)));
+        }
+    }
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = PostingNode;
+
+    fn next(&mut self) -> Option<PostingNode> {
+        let Reverse((doc_id, mut freq, i)) = self.heap.pop()?;
+        self.refill(i);
+        while let Some(&Reverse((next_id, next_freq, j))) = self.heap.peek() {
+            if next_id != doc_id {
+                break;
+            }
+            self.heap.pop();
+            freq += next_freq;
+            self.refill(j);
+        }
+        Some(PostingNode { doc_id, freq })
+    }
+}
+
+fn similarity(a: &str, b: &str, distance: usize) -> f32 {
+    let max_len = a.chars().count().max(b.chars().count());
+    if max_len == 0 {
+        return 1.0;
+    }
+    1.0 - distance as f32 / max_len as f32
+}
+
+/// Levenshtein distance over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j + 1] + 1).min(curr[j] + 1).min(prev[j] + cost);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+/// Terms that most often appear in the same documents as the query terms, most
+/// frequent first (ties in lexicographic order). Query terms themselves are excluded.
+pub fn expand_query_by_cooccurrence(segment: &Segment, query: &str, top_n_expansions: usize) -> Vec<String> {
+    let query_terms = query_terms(query, segment.tokenizer.as_deref()).unwrap_or_default();
+    let query_docs: Vec<HashSet<i64>> = query_terms.iter()
+        .filter_map(|term| segment.dict.get(term))
+        .map(|posting| posting.doc_ids().collect())
+        .collect();
+    if query_docs.is_empty() {
+        return Vec::new();
+    }
+
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for term in segment.dict.terms() {
+        if query_terms.iter().any(|q| q == term) {
+            continue;
+        }
+        let posting = segment.dict.get(term).expect("term listed in dict");
+        let count: usize = query_docs.iter()
+            .map(|docs| posting.doc_ids().filter(|doc_id| docs.contains(doc_id)).count())
+            .sum();
+        if count > 0 {
+            counts.push((term.to_string(), count));
+        }
+    }
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.into_iter().take(top_n_expansions).map(|(term, _)| term).collect()
+}
+
+/// What `search` would do for a query, without scoring any document.
+pub struct QueryPlan {
+    /// Conjunction of the query terms with their document frequency.
+    pub terms: Vec<(String, usize)>,
+    /// Upper bound on matching documents: the smallest df of the terms present in the dict.
+    pub expected_candidates: usize,
+    pub scoring_model: &'static str,
+    pub total_docs: usize,
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<&str> = self.terms.iter().map(|(term, _)| term.as_str()).collect();
+        writeln!(f, "AND({})", terms.join(", "))?;
+        for (term, df) in &self.terms {
+            if *df == 0 {
+                writeln!(f, "  {} df=0 (not indexed, ignored)", term)?;
+            } else {
+                writeln!(f, "  {} df={}", term, df)?;
+            }
+        }
+        writeln!(f, "expected candidates: {}", self.expected_candidates)?;
+        write!(f, "scoring: {}, total docs: {}", self.scoring_model, self.total_docs)
+    }
+}
+
+pub fn explain_plan(segment: &Segment, query: &str) -> QueryPlan {
+    let tokens = query_terms(query, segment.tokenizer.as_deref()).unwrap_or_default();
+    let terms: Vec<(String, usize)> = tokens.into_iter()
+        .map(|token| {
+            let df = segment.dict.get(&token).map(|posting| posting.len()).unwrap_or(0);
+            (token, df)
+        })
+        .collect();
+    let expected_candidates = terms.iter()
+        .map(|(_, df)| *df)
+        .filter(|df| *df > 0)
+        .min()
+        .unwrap_or(0);
+    QueryPlan { terms, expected_candidates, scoring_model: "tf-idf", total_docs: segment.total_docs }
+}
+
+fn score_postings(segment: &Segment, postings: Vec<(PostingList, usize)>, size: i32,
+                  config: &SearchConfig, accept: &dyn Fn(i64) -> bool) -> Vec<TopKDoc> {
+    let iterators: Vec<Iter> = postings.iter()
+        .filter_map(|(posting, doc_freq)| Iter::from_nodes(posting.nodes(), *doc_freq))
+        .collect();
+    let stats = CollectionStats {
+        doc_lengths: &segment.doc_lengths,
+        total_docs: segment.total_docs,
+        avg_doc_len: avg_doc_len(segment),
+    };
+    let accept = |doc_id: i64| source_matches(config, segment.docs.get(&doc_id)) && accept(doc_id);
+    score_iterators(iterators, &stats, size, config, &accept)
+}
+
+/// Whether a document passes `SearchConfig::source_filter`; documents that are
+/// not stored never do once a filter is set.
+fn source_matches(config: &SearchConfig, document: Option<&Arc<Document>>) -> bool {
+    match &config.source_filter {
+        Some(source) => document.is_some_and(|doc| doc.source.as_ref() == Some(source)),
+        None => true,
+    }
+}
+
+/// Clamps the per-term scores to a common cap `c` so that none exceeds
+/// `fraction` of their sum afterwards: `c` is the largest value with
+/// `c == fraction * sum(min(score, c))`, found by trying how many of the top
+/// scores get clamped. Scores are left unchanged when they already comply,
+/// when there is a single score, or when no such cap exists, which happens
+/// when `fraction` is below one over the number of scores.
+fn cap_term_scores(scores: &mut [f32], fraction: f32) {
+    if scores.len() < 2 {
+        return;
+    }
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let total: f32 = sorted.iter().sum();
+    let n = sorted.len();
+    if sorted[n - 1] <= fraction * total {
+        return;
+    }
+    for capped in 1..=n {
+        let denom = 1.0 - fraction * capped as f32;
+        if denom <= 0.0 {
+            return;
+        }
+        let rest: f32 = sorted[..n - capped].iter().sum();
+        let cap = fraction * rest / denom;
+        let below = n - capped;
+        if cap <= sorted[below] && (below == 0 || cap >= sorted[below - 1]) {
+            for score in scores.iter_mut() {
+                *score = score.min(cap);
+            }
+            return;
+        }
+    }
+}
+
+/// What the scorer needs to know about the documents beyond the postings.
+struct CollectionStats<'a> {
+    doc_lengths: &'a HashMap<i64, u32>,
+    total_docs: usize,
+    avg_doc_len: f32,
+}
+
+fn score_iterators(mut iterators: Vec<Iter>, stats: &CollectionStats, size: i32,
+                   config: &SearchConfig, accept: &dyn Fn(i64) -> bool) -> Vec<TopKDoc> {
+    let mut top_k = BinaryHeap::new();
+    debug!("scoring with {} posting iterators, document frequencies {:?}",
+           iterators.len(), iterators.iter().map(|i| i.doc_freq).collect::<Vec<usize>>());
+    if iterators.is_empty() {
+        return Vec::new();
+    }
+    let total_doc_segment = stats.total_docs;
+    let avg_len = stats.avg_doc_len;
+    let mut term_scores = Vec::with_capacity(iterators.len());
+    let mut candidates = 0;
+    loop {
+        iterators.sort_by_key(|k| k.doc_id);
+        let current_doc_id = iterators[0].doc_id;
+        term_scores.clear();
+        let mut hits = 0;
+        let mut end = 0;
+        for iterator in iterators.iter_mut() {
+            if iterator.exhausted {
+                end += 1;
+                continue;
+            }
+
+            while iterator.doc_id < current_doc_id && iterator.advance() {}
+            if iterator.doc_id == current_doc_id {
+                hits += 1;
+                let term_freq = iterator.freq;
+                // precomputed with the cursor, see `Iter::doc_freq`
+                let total_doc_with_term = iterator.doc_freq;
+                let doc_len = stats.doc_lengths.get(&current_doc_id).copied().unwrap_or(0);
+                let term_score = config.scorer.score(term_freq,
+                                                     total_doc_with_term as i32,
+                                                     total_doc_segment,
+                                                     doc_len,
+                                                     avg_len);
+                term_scores.push(term_score);
+                if !iterator.advance() {
+                    iterator.exhausted = true;
+                }
+            } else {
+                end += 1;
+            }
+        }
+        if hits > 0 {
+            candidates += 1;
+        }
+        if hits == iterators.len() && accept(current_doc_id) {
+            if let Some(fraction) = config.max_term_score_fraction {
+                cap_term_scores(&mut term_scores, fraction);
+            }
+            let doc_score = config.combiner.combine(&term_scores);
+            if config.min_score.is_none_or(|min| doc_score >= min) {
+                top_k.push(TopKDoc { id: current_doc_id, score: F32(doc_score) });
+                if top_k.len() == size as usize {
+                    break;
+                }
+            }
+        }
+        if end == iterators.len() {
+            break;
+        }
+    }
+    debug!("scoring loop visited {} candidate documents, kept {}", candidates, top_k.len());
+
+    let mut result = Vec::new();
+    while let Some(doc) = top_k.pop() {
+        result.push(doc);
+    }
+    result
+}
+
+/// Panics if any posting list does not iterate in strictly increasing doc_id
+/// order, which the conjunctive intersection in `score_postings` relies on.
+#[cfg(any(test, feature = "testing"))]
+fn assert_sorted(segment: &Segment) {
+    for term in segment.dict.terms() {
+        let posting = segment.dict.get(term).expect("term listed in dict");
+        let ids: Vec<i64> = posting.doc_ids().collect();
+        for pair in ids.windows(2) {
+            assert!(pair[0] < pair[1], "posting list of {:?} is not sorted: doc {} comes before doc {}",
+                    term, pair[0], pair[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_index_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("danton_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn tokenize_success() {
+        let tokenizer = NaiveTokenizer::new();
+        let tokens = tokenizer.tokenize_with_positions("hello  this is\ta text");
+        assert_eq!(tokens[0].0, "hello");
+        assert_eq!(tokens[1].0, "this");
+        assert_eq!(tokens[2].0, "is");
+        assert_eq!(tokens[3].0, "a");
+        assert_eq!(tokens[4].0, "text");
+        let positions: Vec<u32> = tokens.iter().map(|(_, pos)| *pos).collect();
+        assert_eq!(positions, vec![0, 1, 2, 3, 4]);
+
+        let lowercase = LowercaseTokenizer::new().tokenize_with_positions("Hello World");
+        assert_eq!(lowercase, vec![("hello".to_string(), 0), ("world".to_string(), 1)]);
+        #[allow(deprecated)]
+        let old = tokenizer.tokenize(String::from("hello this"));
+        assert_eq!(old, vec![("hello".to_string(), 0), ("this".to_string(), 1)]);
+    }
+
+    #[test]
+    fn index_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let doc_3 = Document::new(3, "hello");
+        let docs = vec![doc_1, doc_2, doc_3];
+        let segment = index_documents(docs).expect("");
+        let posting = segment.dict.get("test").expect("");
+        assert_eq!(posting.get(1).expect("").freq, 1);
+        assert_eq!(posting.get(1).expect("").doc_id, 1);
+
+        assert_eq!(posting.get(2).expect("").freq, 2);
+        assert_eq!(posting.get(2).expect("").doc_id, 2);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn index_out_of_order_ids_success() {
+        let doc_3 = Document::new(3, "hello there");
+        let doc_1 = Document::new(1, "hello there friend");
+        let doc_2 = Document::new(2, "hello");
+        let mut segment = index_documents(vec![doc_3, doc_1, doc_2]).expect("");
+        let ids: Vec<i64> = segment.dict.get("hello").expect("").doc_ids().collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        let mut found: Vec<i64> = search(&segment, "hello there".to_string(), 10).expect("")
+            .iter().map(|d| d.id).collect();
+        found.sort();
+        assert_eq!(found, vec![1, 3]);
+
+        add_document(&mut segment, Document::new(0, "there")).expect("");
+        let ids: Vec<i64> = segment.dict.get("there").expect("").doc_ids().collect();
+        assert_eq!(ids, vec![0, 1, 3]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_single_token_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let doc_3 = Document::new(3, "hello");
+        let docs = vec![doc_1, doc_2, doc_3];
+        let segment = index_documents(docs).expect("");
+        let found_docs = search(&segment, "test".to_string(), 2).expect("");
+        assert_eq!(found_docs.len(), 2);
+        assert_eq!(found_docs[1].id, 1);
+        assert_eq!(found_docs[0].id, 2);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_plural_tokens_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let doc_3 = Document::new(3, "hello");
+        let doc_4 = Document::new(4, "tablecloth is on there");
+        let docs = vec![doc_1, doc_2, doc_3, doc_4];
+        let segment = index_documents(docs).expect("");
+        let found_docs = search(&segment, "hello there".to_string(), 2).expect("");
+        assert_eq!(found_docs.len(), 1);
+        assert_eq!(found_docs[0].id, 2);
+        assert_sorted(&segment);
+    }
+
+    struct AlphabeticFilter;
+
+    impl TermFilter for AlphabeticFilter {
+        fn accept(&self, term: &str) -> bool {
+            term.chars().all(|c| c.is_alphabetic())
+        }
+    }
+
+    #[test]
+    fn index_with_term_filter_success() {
+        let doc_1 = Document::new(1, "hello 123 world");
+        let doc_2 = Document::new(2, "42 hello");
+        let config = IndexConfig { term_filter: Some(Box::new(AlphabeticFilter)), ..Default::default() };
+        let segment = index_documents_with_config(vec![doc_1, doc_2], &config).expect("");
+
+        assert!(segment.dict.get("123").is_none());
+        assert!(segment.dict.get("42").is_none());
+        assert_eq!(segment.dict.get("hello").expect("").len(), 2);
+        assert_eq!(iter_terms(&segment).collect::<Vec<&str>>(), vec!["hello", "world"]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn avg_doc_len_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let doc_3 = Document::new(3, "hello");
+        let docs = vec![doc_1, doc_2, doc_3];
+        let segment = index_documents(docs).expect("");
+        assert_eq!(segment.doc_lengths[&1], 4);
+        assert_eq!(segment.doc_lengths[&2], 5);
+        assert_eq!(segment.doc_lengths[&3], 1);
+        assert_eq!(avg_doc_len(&segment), (4 + 5 + 1) as f32 / 3.0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn batch_search_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let doc_3 = Document::new(3, "hello");
+        let doc_4 = Document::new(4, "tablecloth is on there");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3, doc_4]).expect("");
+        let queries: Vec<String> = vec!["hello", "test", "hello there", "is", "there", "hello test",
+                                        "missing", "is there", "second", "tablecloth on"]
+            .into_iter().map(|q| q.to_string()).collect();
+
+        let results = batch_search(&segment, queries.clone(), 3);
+        assert_eq!(results.len(), queries.len());
+        for (query, found_docs) in queries.into_iter().zip(results) {
+            let expected = search(&segment, query, 3).expect("");
+            let found_ids: Vec<i64> = found_docs.iter().map(|d| d.id).collect();
+            let expected_ids: Vec<i64> = expected.iter().map(|d| d.id).collect();
+            assert_eq!(found_ids, expected_ids);
+        }
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn batch_search_matches_search_with_config_success() {
+        let mut doc_1 = Document::new(1, "hello rust");
+        doc_1.fields.insert("title".to_string(), vec!["rust".to_string()]);
+        let doc_2 = Document::new(2, "hello go");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+        let mut config = SearchConfig { max_query_terms: 2, normalize_scores: true, ..SearchConfig::default() };
+        config.field_aliases.insert("t".to_string(), "title".to_string());
+        let queries: Vec<String> = vec!["t:rust", "hello", "hello rust go", "", "hello go"]
+            .into_iter().map(|q| q.to_string()).collect();
+
+        let results = batch_search_with_config(&segment, queries.clone(), 10, &config);
+        for (query, found_docs) in queries.into_iter().zip(results) {
+            let expected: Vec<(i64, f32)> = search_with_config(&segment, query.clone(), 10, &config)
+                .map(|docs| docs.iter().map(|d| (d.id, d.score.0)).collect())
+                .unwrap_or_default();
+            let found: Vec<(i64, f32)> = found_docs.iter().map(|d| (d.id, d.score.0)).collect();
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+        assert_eq!(batch_search_with_config(&segment, vec!["t:rust".to_string()], 10, &config)[0].len(), 1);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn edit_distance_success() {
+        assert_eq!(edit_distance("hello", "hello"), 0);
+        assert_eq!(edit_distance("hello", "helo"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn search_fuzzy_min_similarity_success() {
+        let doc_1 = Document::new(1, "hello there");
+        let doc_2 = Document::new(2, "jello");
+        let doc_3 = Document::new(3, "hxyzw");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3]).expect("");
+
+        let found_docs = search_fuzzy(&segment, "hello", 4, 0.0, 10).expect("");
+        assert_eq!(found_docs.len(), 3);
+
+        // "hxyzw" is 4 edits away from "hello": similarity 0.2
+        let found_docs = search_fuzzy(&segment, "hello", 4, 0.4, 10).expect("");
+        let mut ids: Vec<i64> = found_docs.iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn merge_posting_lists_or_success() {
+        let lists: Vec<PostingList> = (0..12)
+            .map(|k| (0..20).filter(|id| id % (k + 1) == 0)
+                .map(|id| PostingNode { doc_id: id, freq: (k + 1) as i32 })
+                .collect())
+            .collect();
+        let merged = merge_posting_lists_or(lists.iter().collect());
+
+        let mut naive: HashMap<i64, i32> = HashMap::new();
+        for posting in &lists {
+            for node in posting.nodes() {
+                *naive.entry(node.doc_id).or_insert(0) += node.freq;
+            }
+        }
+        let mut expected: Vec<(i64, i32)> = naive.into_iter().collect();
+        expected.sort();
+        let found: Vec<(i64, i32)> = merged.nodes().map(|n| (n.doc_id, n.freq)).collect();
+        assert_eq!(found, expected);
+        assert!(merge_posting_lists_or(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn expand_query_by_cooccurrence_success() {
+        let doc_1 = Document::new(1, "dog canine bark");
+        let doc_2 = Document::new(2, "dog canine leash");
+        let doc_3 = Document::new(3, "dog canine bark");
+        let doc_4 = Document::new(4, "cat leash");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3, doc_4]).expect("");
+
+        assert_eq!(expand_query_by_cooccurrence(&segment, "dog", 2), vec!["canine", "bark"]);
+        assert_eq!(expand_query_by_cooccurrence(&segment, "dog", 10), vec!["canine", "bark", "leash"]);
+        assert!(expand_query_by_cooccurrence(&segment, "missing", 2).is_empty());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn explain_plan_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let doc_3 = Document::new(3, "hello");
+        let doc_4 = Document::new(4, "tablecloth is on there");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3, doc_4]).expect("");
+        let plan = explain_plan(&segment, "hello there");
+        assert_eq!(plan.terms, vec![("hello".to_string(), 3), ("there".to_string(), 2)]);
+        assert_eq!(plan.expected_candidates, 2);
+        assert_eq!(plan.total_docs, 4);
+        assert_eq!(format!("{}", plan),
+                   "AND(hello, there)\n  hello df=3\n  there df=2\nexpected candidates: 2\nscoring: tf-idf, total docs: 4");
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_multi_value_field_success() {
+        let mut doc_1 = Document::new(1, "a book about stars");
+        doc_1.fields.insert("category".to_string(), vec!["books".to_string(), "science".to_string()]);
+        let mut doc_2 = Document::new(2, "a telescope");
+        doc_2.fields.insert("category".to_string(), vec!["science".to_string()]);
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+
+        let found_docs = search(&segment, "category:books".to_string(), 10).expect("");
+        assert_eq!(found_docs.len(), 1);
+        assert_eq!(found_docs[0].id, 1);
+
+        let mut ids: Vec<i64> = search(&segment, "category:science".to_string(), 10).expect("")
+            .iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(search(&segment, "books".to_string(), 10).expect("").is_empty());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn per_field_idf_success() {
+        let docs: Vec<Document> = (0..10)
+            .map(|id| {
+                let mut doc = Document::new(id, "learning rust");
+                if id < 2 {
+                    doc.fields.insert("tag".to_string(), vec!["rust".to_string()]);
+                }
+                doc
+            })
+            .collect();
+        let segment = index_documents(docs).expect("");
+        let global = SearchConfig { per_field_idf: false, ..SearchConfig::default() };
+
+        let field_hits = search_with_config(&segment, "tag:rust".to_string(), 10, &SearchConfig::default()).expect("");
+        let global_hits = search_with_config(&segment, "tag:rust".to_string(), 10, &global).expect("");
+        assert_eq!(field_hits.len(), 2);
+        assert_eq!(global_hits.len(), 2);
+        for (field, global) in field_hits.iter().zip(&global_hits) {
+            assert_eq!(field.id, global.id);
+            assert!(field.score.0 > global.score.0);
+        }
+
+        let flat = flatten(segment.clone());
+        let flat_hits = search_flat_with_config(&flat, "tag:rust".to_string(), 10, &global).expect("");
+        assert_eq!(flat_hits.iter().map(|d| d.score.0).collect::<Vec<f32>>(),
+                   global_hits.iter().map(|d| d.score.0).collect::<Vec<f32>>());
+
+        // a value found only in fields counts the documents of every field
+        let mut docs: Vec<Document> = (0..4).map(|id| Document::new(id, "other")).collect();
+        docs[0].fields.insert("tag".to_string(), vec!["zig".to_string()]);
+        docs[1].fields.insert("lang".to_string(), vec!["zig".to_string()]);
+        let fields = index_documents(docs).expect("");
+        let field_hits = search_with_config(&fields, "tag:zig".to_string(), 10, &SearchConfig::default()).expect("");
+        let global_hits = search_with_config(&fields, "tag:zig".to_string(), 10, &global).expect("");
+        assert!(field_hits[0].score.0 > global_hits[0].score.0);
+        let flat_hits = search_flat_with_config(&flatten(fields), "tag:zig".to_string(), 10, &global).expect("");
+        assert_eq!(flat_hits[0].score.0, global_hits[0].score.0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn normalize_scores_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let doc_3 = Document::new(3, "hello");
+        let doc_4 = Document::new(4, "test test test there");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3, doc_4]).expect("");
+
+        let mut found_docs = search(&segment, "test".to_string(), 10).expect("");
+        normalize_scores(&mut found_docs);
+        assert_eq!(found_docs[0].score.0, 1.0);
+        for doc in &found_docs {
+            assert!(doc.score.0 >= 0.0 && doc.score.0 <= 1.0);
+        }
+
+        let config = SearchConfig { normalize_scores: true, ..SearchConfig::default() };
+        let found_docs = search_with_config(&segment, "test there".to_string(), 10, &config).expect("");
+        assert_eq!(found_docs[0].score.0, 1.0);
+        for doc in &found_docs {
+            assert!(doc.score.0 >= 0.0 && doc.score.0 <= 1.0);
+        }
+        assert_sorted(&segment);
+    }
+
+    struct ConstantScorer;
+
+    impl DocumentScorer for ConstantScorer {
+        fn score(&self, _: i32, _: i32, _: usize, _: u32, _: f32) -> f32 {
+            1.0
+        }
+    }
+
+    #[test]
+    fn search_with_custom_scorer_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let doc_3 = Document::new(3, "hello test test test");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3]).expect("");
+        let config = SearchConfig { scorer: Box::new(ConstantScorer), ..SearchConfig::default() };
+
+        let found_docs = search_with_config(&segment, "test".to_string(), 10, &config).expect("");
+        assert_eq!(found_docs.len(), 3);
+        assert!(found_docs.iter().all(|doc| doc.score.0 == 1.0));
+
+        let found_docs = search_with_config(&segment, "hello test".to_string(), 10, &config).expect("");
+        assert_eq!(found_docs.len(), 3);
+        assert!(found_docs.iter().all(|doc| doc.score.0 == 2.0));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_with_score_combiner_success() {
+        let doc_1 = Document::new(1, "rare common");
+        let doc_2 = Document::new(2, "common");
+        let doc_3 = Document::new(3, "common other");
+        let doc_4 = Document::new(4, "other");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3, doc_4]).expect("");
+        let score = |query: &str, config: &SearchConfig| {
+            search_with_config(&segment, query.to_string(), 10, config).expect("")[0].score.0
+        };
+
+        let max = SearchConfig { combiner: Box::new(MaxCombiner), ..SearchConfig::default() };
+        assert_eq!(score("rare", &max), score("rare common", &max));
+
+        let sum = SearchConfig::default();
+        assert_ne!(score("rare", &sum), score("rare common", &sum));
+
+        let avg = SearchConfig { combiner: Box::new(AvgCombiner), ..SearchConfig::default() };
+        assert_eq!(score("rare common", &avg), score("rare common", &sum) / 2.0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_random_sample_success() {
+        let docs = (0..50).map(|id| Document::new(id, "hello sample")).collect();
+        let segment = index_documents(docs).expect("");
+        let ids = |seed| search_random_sample(&segment, "sample", 5, seed)
+            .iter().map(|d| d.id).collect::<Vec<i64>>();
+
+        let first = ids(42);
+        assert_eq!(first.len(), 5);
+        assert_eq!(first, ids(42));
+        assert_ne!(first, ids(7));
+        let mut unique = first.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 5);
+        assert_eq!(search_random_sample(&segment, "sample", 100, 1).len(), 50);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_with_field_alias_success() {
+        let mut doc_1 = Document::new(1, "first");
+        doc_1.fields.insert("question".to_string(), vec!["how to learn rust".to_string()]);
+        let mut doc_2 = Document::new(2, "second rust");
+        doc_2.fields.insert("question".to_string(), vec!["how to cook".to_string()]);
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+
+        let mut config = SearchConfig::default();
+        config.field_aliases.insert("q".to_string(), "question".to_string());
+        let found_docs = search_with_config(&segment, "q:rust".to_string(), 10, &config).expect("");
+        assert_eq!(found_docs.len(), 1);
+        assert_eq!(found_docs[0].id, 1);
+
+        let found_docs = search_with_config(&segment, "question:rust".to_string(), 10, &config).expect("");
+        assert_eq!(found_docs.len(), 1);
+        assert!(search(&segment, "q:rust".to_string(), 10).expect("").is_empty());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn linear_reranker_success() {
+        let docs = (1..=3).map(|id| Document::new(id, "hello reranking")).chain(vec![Document::new(4, "filler")]);
+        let segment = index_documents(docs.collect()).expect("");
+        let results = search(&segment, "hello".to_string(), 10).expect("");
+        assert_eq!(results.len(), 3);
+
+        let mut weights = HashMap::new();
+        weights.insert("popularity".to_string(), 2.0);
+        weights.insert("freshness".to_string(), 0.5);
+        let reranker = LinearReranker { weights };
+        let features = |doc_id: i64| {
+            let mut features = HashMap::new();
+            features.insert("popularity".to_string(), if doc_id == 3 { 10.0 } else { 1.0 });
+            features.insert("freshness".to_string(), doc_id as f32);
+            features.insert("unweighted".to_string(), 100.0);
+            features
+        };
+        let reranked = reranker.rerank(results, &features);
+        let ids: Vec<i64> = reranked.iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(reranked[0].score.0, 21.5);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_with_acl_success() {
+        let docs = (1..=10).map(|id| Document::new(id, "hello acl")).chain(vec![Document::new(11, "filler")]);
+        let segment = index_documents(docs.collect()).expect("");
+        let visible = |doc_id: i64| doc_id % 2 == 0;
+
+        let found_docs = search_with_acl(&segment, "hello", 3, visible);
+        assert_eq!(found_docs.len(), 3);
+        assert!(found_docs.iter().all(|d| visible(d.id)));
+
+        let found_docs = search_with_acl(&segment, "hello", 10, visible);
+        assert_eq!(found_docs.len(), 5);
+        assert!(found_docs.iter().all(|d| visible(d.id)));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_empty_query_fails() {
+        let segment = index_documents(vec![Document::new(1, "hello")]).expect("");
+        assert!(matches!(search(&segment, "   ".to_string(), 2), Err(SearchError::EmptyQuery)));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn top_k_doc_hash_by_id_success() {
+        let mut hits = HashSet::new();
+        hits.insert(TopKDoc { id: 1, score: F32(0.5) });
+        hits.insert(TopKDoc { id: 1, score: F32(2.0) });
+        hits.insert(TopKDoc { id: 2, score: F32(0.5) });
+        assert_eq!(hits.len(), 2);
+
+        let a = TopKDoc { id: 1, score: F32(0.5) };
+        assert!(a == TopKDoc { id: 1, score: F32(2.0) });
+        assert!(!a.same_hit(&TopKDoc { id: 1, score: F32(2.0) }));
+        assert!(a.same_hit(&TopKDoc { id: 1, score: F32(0.5) }));
+    }
+
+    #[test]
+    fn unicode_normalizer_success() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(composed, decomposed);
+
+        let tokenizer = UnicodeNormalizer::new(NaiveTokenizer::new(), NormalizationForm::Nfc);
+        let tokens = tokenizer.tokenize_with_positions(&format!("{} {}", composed, decomposed));
+        assert_eq!(tokens[0].0, composed);
+        assert_eq!(tokens[1].0, composed);
+
+        let text: Vec<String> = tokens.into_iter().map(|(token, _)| token).collect();
+        let segment = index_documents(vec![Document::new(1, &text.join(" "))]).expect("");
+        assert_eq!(segment.dict.len(), 1);
+        let posting = segment.dict.get(composed).expect("");
+        assert_eq!(posting.len(), 1);
+        assert_eq!(posting.get(1).expect("").freq, 2);
+
+        let tokenizer = UnicodeNormalizer::new(NaiveTokenizer::new(), NormalizationForm::Nfkc);
+        assert_eq!(tokenizer.tokenize_with_positions("\u{fb01}le")[0].0, "file");
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn tokenize_stream_success() {
+        let word = "d\u{e9}j\u{e0}vu";
+        let text: Vec<String> = (0..5000).map(|i| format!("{}{}", word, i)).collect();
+        let text = format!("  {}\n", text.join(" \t"));
+        assert!(text.len() > 2 * STREAM_CHUNK_SIZE);
+
+        let streamed = NaiveTokenizer::new().tokenize_stream(std::io::Cursor::new(text.clone().into_bytes()));
+        assert_eq!(streamed, NaiveTokenizer::new().tokenize_with_positions(&text));
+        assert!(NaiveTokenizer::new().tokenize_stream(std::io::Cursor::new(Vec::new())).is_empty());
+    }
+
+    #[test]
+    fn dense_posting_list_search_success() {
+        let docs: Vec<Document> = (0..40)
+            .map(|id| {
+                let text = if id % 4 == 0 { "common common rare" } else { "common other" };
+                Document::new(id, text)
+            })
+            .collect();
+        let segment = index_documents(docs).expect("");
+        assert!(segment.dict.get("common").expect("").is_dense());
+        assert!(!segment.dict.get("rare").expect("").is_dense());
+
+        let mut sparse = segment.clone();
+        let mut posting = sparse.dict.get("common").expect("");
+        posting.make_sparse();
+        sparse.dict.insert("common", posting);
+
+        let queries = ["common", "common rare", "other common", "rare"];
+        for query in queries {
+            let dense_hits = search(&segment, query.to_string(), 100).expect("");
+            let sparse_hits = search(&sparse, query.to_string(), 100).expect("");
+            let dense_hits: Vec<(i64, f32)> = dense_hits.iter().map(|d| (d.id, d.score.0)).collect();
+            let sparse_hits: Vec<(i64, f32)> = sparse_hits.iter().map(|d| (d.id, d.score.0)).collect();
+            assert_eq!(dense_hits, sparse_hits, "query {:?}", query);
+        }
+        let dense = segment.dict.get("common").expect("");
+        let merged = merge_posting_lists_or(vec![&dense, &sparse.dict.get("rare").expect("")]);
+        assert_eq!(merged.get(4).expect("").freq, 3);
+        assert_eq!(merged.get(5).expect("").freq, 1);
+        assert_eq!(term_freq(&segment, "common", 8), 2);
+        assert_sorted(&segment);
+        assert_sorted(&sparse);
+    }
+
+    #[test]
+    fn dense_posting_list_update_success() {
+        let mut posting: PostingList = (0..10).map(|doc_id| PostingNode { doc_id, freq: 1 }).collect();
+        assert!(posting.make_dense());
+        posting.add(3, 2);
+        posting.add(70, 1);
+        assert!(posting.remove(5));
+        assert!(!posting.remove(5));
+        assert_eq!(posting.len(), 10);
+        assert_eq!(posting.get(3).expect("").freq, 3);
+        assert!(posting.get(5).is_none());
+
+        posting.add(-1, 1);
+        assert!(!posting.is_dense());
+        assert_eq!(posting.doc_ids().collect::<Vec<i64>>(), vec![-1, 0, 1, 2, 3, 4, 6, 7, 8, 9, 70]);
+        assert!(!posting.make_dense());
+    }
+
+    #[test]
+    fn dense_posting_list_large_doc_id_success() {
+        let mut posting: PostingList = (0..10).map(|doc_id| PostingNode { doc_id, freq: 1 }).collect();
+        assert!(posting.make_dense());
+        posting.add(200, 1);
+        assert!(posting.is_dense());
+
+        let large = i64::MAX >> 8;
+        posting.add(large, 2);
+        assert!(!posting.is_dense());
+        assert_eq!(posting.len(), 12);
+        assert_eq!(posting.get(large).expect("").freq, 2);
+        assert_eq!(posting.doc_ids().last(), Some(large));
+
+        let mut segment = index_documents(vec![Document::new(1, "common"), Document::new(2, "common rare")]).expect("");
+        assert!(segment.dict.get("common").expect("").is_dense());
+        add_document(&mut segment, Document::new(large, "common")).expect("");
+        assert!(!segment.dict.get("common").expect("").is_dense());
+        assert_eq!(search(&segment, "common".to_string(), 10).expect("").len(), 3);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_unbuilt_segment_fails() {
+        let mut segment = Segment::new();
+        assert!(!segment.is_built());
+        assert!(matches!(search(&segment, "hello".to_string(), 10), Err(SearchError::IndexNotReady)));
+
+        add_document(&mut segment, Document::new(1, "hello")).expect("");
+        assert_eq!(search(&segment, "hello".to_string(), 10).expect("").len(), 1);
+
+        let segment = index_documents(vec![Document::new(1, "hello")]).expect("");
+        assert!(segment.is_built());
+        segment.built.store(false, AtomicOrdering::Release);
+        assert!(matches!(search(&segment, "hello".to_string(), 10), Err(SearchError::IndexNotReady)));
+        assert!(matches!(search_fuzzy(&segment, "hello", 1, 0.0, 10), Err(SearchError::IndexNotReady)));
+        assert!(search_with_acl(&segment, "hello", 10, |_| true).is_empty());
+        assert!(batch_search(&segment, vec!["hello".to_string()], 10)[0].is_empty());
+        assert!(!segment.clone().is_built());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn verify_integrity_success() {
+        let mut segment = index_documents(vec![
+            Document::new(1, "hello world"),
+            Document::new(2, "hello there"),
+        ]).expect("");
+        assert!(verify_integrity(&segment).is_ok());
+
+        let mut nodes = BTreeMap::new();
+        nodes.insert(1, PostingNode { doc_id: 2, freq: 1 });
+        nodes.insert(2, PostingNode { doc_id: 1, freq: 0 });
+        segment.dict.insert("world", PostingList { repr: PostingListRepr::Sparse(nodes) });
+        segment.dict.insert("ghost", PostingList::from_iter([PostingNode { doc_id: 9, freq: 1 }]));
+        segment.total_docs = 3;
+
+        let errors = verify_integrity(&segment).expect_err("");
+        assert_eq!(errors, vec![
+            IntegrityError::TotalDocsMismatch { total_docs: 3, stored: 2 },
+            IntegrityError::UnknownDocument { term: "ghost".to_string(), doc_id: 9 },
+            IntegrityError::ZeroFrequency { term: "world".to_string(), doc_id: 1 },
+            IntegrityError::UnsortedPostings { term: "world".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn index_duplicate_id_fails() {
+        let docs = vec![Document::new(1, "hello"), Document::new(2, "there"), Document::new(1, "again")];
+        assert!(matches!(index_documents(docs), Err(SearchError::DuplicateDocumentId(1))));
+    }
+
+    /// Writer handed to env_logger so a test can read back what was logged.
+    #[derive(Clone)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn debug_logging_success() {
+        let buffer = SharedBuffer(Default::default());
+        env_logger::Builder::new()
+            .filter_module("danton", log::LevelFilter::Debug)
+            .format(|f, record| writeln!(f, "{} {}", record.level(), record.args()))
+            .target(env_logger::Target::Pipe(Box::new(buffer.clone())))
+            .init();
+
+        let segment = index_documents(vec![Document::new(1, "hello world")]).expect("");
+        search(&segment, "hello world".to_string(), 10).expect("");
+        let path = temp_index_path("debug_logging");
+        flush_to_disk(&segment, &path).expect("");
+        load_from_disk(&path).expect("");
+
+        let logged = String::from_utf8(buffer.0.lock().unwrap().clone()).expect("");
+        for expected in [
+            "DEBUG indexed 1 documents, 2 tokens into 2 terms",
+            "DEBUG query \"hello world\" tokenized into [\"hello\", \"world\"], 2 found in the dictionary",
+            "DEBUG scoring with 2 posting iterators",
+            "DEBUG scoring loop visited 1 candidate documents, kept 1",
+            &format!("DEBUG flushed segment to {}", path),
+            &format!("DEBUG loaded segment from {}", path),
+        ] {
+            assert!(logged.contains(expected), "missing {:?} in {:?}", expected, logged);
+        }
+        assert_sorted(&segment);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    type SpanFields = std::sync::Arc<std::sync::Mutex<Vec<(String, HashMap<String, String>)>>>;
+
+    /// Layer that keeps the name and fields of every span it sees.
+    struct SpanCollector(SpanFields);
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>
+        tracing_subscriber::Layer<S> for SpanCollector {
+        fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id,
+                       _: tracing_subscriber::layer::Context<'_, S>) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            fields.insert("id".to_string(), format!("{:?}", id));
+            self.0.lock().unwrap().push((attrs.metadata().name().to_string(), fields));
+        }
+
+        fn on_record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>,
+                     _: tracing_subscriber::layer::Context<'_, S>) {
+            let id = format!("{:?}", id);
+            let mut spans = self.0.lock().unwrap();
+            if let Some((_, fields)) = spans.iter_mut().rev().find(|(_, fields)| fields["id"] == id) {
+                values.record(&mut FieldVisitor(fields));
+            }
+        }
+    }
+
+    #[test]
+    fn tracing_spans_success() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = SpanFields::default();
+        let subscriber = tracing_subscriber::registry().with(SpanCollector(spans.clone()));
+        let path = temp_index_path("tracing_spans");
+        tracing::subscriber::with_default(subscriber, || {
+            let segment = index_documents(vec![Document::new(1, "hello world"), Document::new(2, "hello")])
+                .expect("");
+            search(&segment, "hello".to_string(), 10).expect("");
+            flush_to_disk(&segment, &path).expect("");
+            load_from_disk(&path).expect("");
+        });
+
+        let spans = spans.lock().unwrap();
+        let fields = |name: &str| &spans.iter().find(|(n, _)| n == name).expect(name).1;
+        let search_fields = fields("search_with_config");
+        assert_eq!(search_fields["query"], "hello");
+        assert_eq!(search_fields["total_docs"], "2");
+        assert_eq!(search_fields["result_count"], "2");
+        assert!(search_fields.contains_key("elapsed_ms"));
+        for name in ["flush_to_disk", "load_from_disk"] {
+            assert_eq!(fields(name)["total_docs"], "2", "{}", name);
+            assert!(fields(name).contains_key("elapsed_ms"), "{}", name);
+            assert!(fields(name)["path"].contains("tracing_spans"), "{}", name);
+        }
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn index_memory_budget_fails() {
+        let docs: Vec<Document> = (0..1000)
+            .map(|id| Document::new(id, &format!("document number {} of a large ingestion run", id)))
+            .collect();
+        let config = IndexConfig { max_memory_bytes: Some(1024), ..Default::default() };
+        match index_documents_with_config(docs.clone(), &config) {
+            Err(SearchError::MemoryLimitExceeded { limit, estimated }) => {
+                assert_eq!(limit, 1024);
+                assert!(estimated > 1024);
+                assert!(estimated < 2048, "budget should be checked after every document");
+            }
+            _ => panic!("expected MemoryLimitExceeded"),
+        }
+
+        let config = IndexConfig { max_memory_bytes: Some(usize::MAX), ..Default::default() };
+        assert_eq!(index_documents_with_config(docs, &config).expect("").total_docs, 1000);
+    }
+
+    #[test]
+    fn load_terms_from_disk_success() {
+        let docs = vec![
+            Document::new(1, "hello world"),
+            Document::new(2, "hello there world"),
+            Document::new(3, "goodbye world"),
+        ];
+        let segment = index_documents(docs).expect("");
+        let path = temp_index_path("load_terms");
+        flush_to_disk(&segment, &path).expect("");
+
+        let partial = load_terms_from_disk(&path, &["hello", "world", "missing"]).expect("");
+        assert_eq!(partial.dict.len(), 2);
+        assert_eq!(partial.total_docs, 3);
+        assert!(!partial.dict.contains("goodbye"));
+        for query in ["hello", "hello world", "world"] {
+            let expected: Vec<(i64, f32)> = search(&segment, query.to_string(), 10).expect("")
+                .iter().map(|d| (d.id, d.score.0)).collect();
+            let found: Vec<(i64, f32)> = search(&partial, query.to_string(), 10).expect("")
+                .iter().map(|d| (d.id, d.score.0)).collect();
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+
+        fs::remove_file(format!("{}/{}", path, POSTING_INDEX_FILE_NAME)).expect("");
+        assert_eq!(load_terms_from_disk(&path, &["hello"]).expect("").dict.len(), 4);
+        assert_sorted(&partial);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn render_template_success() {
+        let mut document = Document::new(1, "body text");
+        document.fields.insert("title".to_string(), vec!["Dune".to_string()]);
+        document.fields.insert("author".to_string(), vec!["Frank Herbert".to_string()]);
+        document.fields.insert("tags".to_string(), vec!["sf".to_string(), "classic".to_string()]);
+
+        assert_eq!(render(&ResultTemplate::new("{{title}} by {{author}}"), &document), "Dune by Frank Herbert");
+        assert_eq!(render(&ResultTemplate::new("[{{ tags }}] {{missing}}!"), &document), "[sf, classic] !");
+        assert_eq!(render(&ResultTemplate::new("{{title}} {{open"), &document), "Dune {{open");
+    }
+
+    #[test]
+    fn merge_iter_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "red green"),
+            Document::new(2, "green green"),
+            Document::new(3, "red blue"),
+            Document::new(5, "blue blue blue"),
+        ]).expect("");
+        let lists: Vec<PostingList> = ["red", "green", "blue"].iter()
+            .map(|term| segment.dict.get(term).expect(""))
+            .collect();
+        let mut dense = lists[0].clone();
+        assert!(dense.make_dense());
+
+        let merged = merge_posting_lists_or(vec![&dense, &lists[1], &lists[2]]);
+        let lazy: Vec<(i64, i32)> = MergeIter::new(vec![&dense, &lists[1], &lists[2]])
+            .map(|node| (node.doc_id, node.freq))
+            .collect();
+        assert_eq!(lazy, merged.nodes().map(|node| (node.doc_id, node.freq)).collect::<Vec<(i64, i32)>>());
+        assert_eq!(lazy, vec![(1, 2), (2, 2), (3, 2), (5, 3)]);
+        assert_eq!(MergeIter::new(Vec::new()).count(), 0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn segment_from_triples_success() {
+        let texts = ["rust search engine", "rust rust compiler", "search engine in go", "go go go"];
+        let documents: Vec<Document> = texts.iter().enumerate()
+            .map(|(id, text)| Document::new(id as i64, text))
+            .collect();
+        let indexed = index_documents(documents).expect("");
+
+        let mut counts: HashMap<(String, i64), i32> = HashMap::new();
+        for (id, text) in texts.iter().enumerate() {
+            for word in text.split_whitespace() {
+                *counts.entry((word.to_string(), id as i64)).or_insert(0) += 1;
+            }
+        }
+        let segment = Segment::from_triples(counts.into_iter().map(|((term, id), freq)| (term, id, freq))).expect("");
+
+        assert_eq!(segment.total_docs, 4);
+        assert_eq!(segment.doc_lengths, indexed.doc_lengths);
+        for query in ["rust", "go", "search engine", "rust compiler", "missing"] {
+            let scores = |segment: &Segment| -> Vec<(i64, f32)> {
+                search(segment, query.to_string(), 10).expect("")
+                    .iter().map(|d| (d.id, d.score.0)).collect()
+            };
+            assert_eq!(scores(&segment), scores(&indexed), "query {:?}", query);
+        }
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn from_triples_delete_document_success() {
+        let triples = [("rust", 1, 2), ("search", 1, 1), ("rust", 2, 1), ("go", 3, 1)];
+        let mut segment = Segment::from_triples(triples.iter().map(|&(term, id, freq)| (term.to_string(), id, freq)))
+            .expect("");
+        delete_document(&mut segment, 1).expect("");
+        let ids = |segment: &Segment, query: &str| -> Vec<i64> {
+            search(segment, query.to_string(), 10).expect("").iter().map(|d| d.id).collect()
+        };
+        assert_eq!(ids(&segment, "rust"), vec![2]);
+        assert!(ids(&segment, "search").is_empty());
+        assert!(!term_exists(&segment, "search"));
+
+        // replacing a document drops the postings of its old version
+        add_document(&mut segment, Document::new(2, "go")).expect("");
+        assert!(ids(&segment, "rust").is_empty());
+        let mut go = ids(&segment, "go");
+        go.sort();
+        assert_eq!(go, vec![2, 3]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn from_triples_invalid_frequency_fails() {
+        let triples = |list: &[(&str, i64, i32)]| -> Vec<(String, i64, i32)> {
+            list.iter().map(|&(term, id, freq)| (term.to_string(), id, freq)).collect()
+        };
+        assert!(matches!(Segment::from_triples(triples(&[("rust", 1, 1), ("go", 2, 0)]).into_iter()),
+                         Err(SearchError::InvalidFrequency { doc_id: 2, freq: 0, .. })));
+        assert!(matches!(Segment::from_triples(triples(&[("rust", 1, -3)]).into_iter()),
+                         Err(SearchError::InvalidFrequency { freq: -3, .. })));
+        assert!(matches!(Segment::from_triples(triples(&[("rust", 1, i32::MAX), ("rust", 1, i32::MAX)]).into_iter()),
+                         Err(SearchError::InvalidFrequency { doc_id: 1, .. })));
+
+        // lengths saturate instead of overflowing
+        let segment = Segment::from_triples(triples(&[("a", 1, i32::MAX), ("b", 1, i32::MAX), ("c", 1, 2)]).into_iter())
+            .expect("");
+        assert_eq!(segment.doc_lengths[&1], u32::MAX);
+
+        let tsv = "rust\t1\t2147483647\nrust\t1\t2147483647\n";
+        assert!(matches!(load_tsv(tsv.as_bytes()), Err(error) if error.kind() == std::io::ErrorKind::InvalidData));
+        assert!(matches!(load_tsv("rust\t1\t0\n".as_bytes()),
+                         Err(error) if error.kind() == std::io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn dump_tsv_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "red green red"),
+            Document::new(2, "green"),
+        ]).expect("");
+        let mut buffer = Vec::new();
+        dump_tsv(&segment, &mut buffer).expect("");
+
+        let output = String::from_utf8(buffer).expect("");
+        let triples: Vec<(String, i64, i32)> = output.lines()
+            .map(|line| {
+                let columns: Vec<&str> = line.split('\t').collect();
+                assert_eq!(columns.len(), 3, "malformed line {:?}", line);
+                (columns[0].to_string(), columns[1].parse().expect(""), columns[2].parse().expect(""))
+            })
+            .collect();
+        assert_eq!(triples, vec![
+            ("green".to_string(), 1, 1),
+            ("green".to_string(), 2, 1),
+            ("red".to_string(), 1, 2),
+        ]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn load_tsv_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "rust search engine"),
+            Document::new(2, "rust rust compiler"),
+            Document::new(3, "search engine in go"),
+        ]).expect("");
+        let mut buffer = Vec::new();
+        dump_tsv(&segment, &mut buffer).expect("");
+        let loaded = load_tsv(buffer.as_slice()).expect("");
+
+        assert_eq!(loaded.total_docs, segment.total_docs);
+        assert_eq!(iter_terms(&loaded).collect::<Vec<&str>>(), iter_terms(&segment).collect::<Vec<&str>>());
+        for query in ["rust", "search engine", "go", "missing"] {
+            let found: Vec<(i64, f32)> = search(&loaded, query.to_string(), 10).expect("")
+                .iter().map(|d| (d.id, d.score.0)).collect();
+            let expected: Vec<(i64, f32)> = search(&segment, query.to_string(), 10).expect("")
+                .iter().map(|d| (d.id, d.score.0)).collect();
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+
+        assert!(matches!(load_tsv("rust\t1\n".as_bytes()),
+                         Err(error) if error.kind() == std::io::ErrorKind::InvalidData));
+        assert_sorted(&loaded);
+    }
+
+    #[test]
+    fn load_tsv_delete_document_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "rust search engine"),
+            Document::new(2, "rust compiler"),
+        ]).expect("");
+        let mut buffer = Vec::new();
+        dump_tsv(&segment, &mut buffer).expect("");
+        let mut loaded = load_tsv(buffer.as_slice()).expect("");
+
+        delete_document(&mut loaded, 1).expect("");
+        let ids: Vec<i64> = search(&loaded, "rust".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![2]);
+        assert!(search(&loaded, "engine".to_string(), 10).expect("").is_empty());
+        assert_eq!(iter_terms(&loaded).collect::<Vec<&str>>(), vec!["compiler", "rust"]);
+
+        add_document(&mut loaded, Document::new(2, "go")).expect("");
+        assert!(search(&loaded, "compiler".to_string(), 10).expect("").is_empty());
+        assert_eq!(iter_terms(&loaded).collect::<Vec<&str>>(), vec!["go"]);
+        assert_sorted(&loaded);
+    }
+
+    #[test]
+    fn export_gz_success() {
+        let mut documents: Vec<Document> = (0..200)
+            .map(|id| Document::new(id, &format!("archived document number {} of the search engine corpus", id % 10)))
+            .collect();
+        documents[3].fields.insert("title".to_string(), vec!["Rust".to_string()]);
+        let segment = index_documents(documents).expect("");
+        let dir = tempfile::tempdir().expect("");
+        let path = dir.path().join("segment.json.gz");
+        let path = path.to_str().expect("");
+        export_gz(&segment, path).expect("");
+        let imported = import_gz(path).expect("");
+
+        assert_eq!(imported.total_docs, segment.total_docs);
+        assert_eq!(imported.total_tokens_indexed, segment.total_tokens_indexed);
+        assert_eq!(imported.doc_lengths, segment.doc_lengths);
+        assert_eq!(iter_terms(&imported).collect::<Vec<&str>>(), iter_terms(&segment).collect::<Vec<&str>>());
+        for term in iter_terms(&segment) {
+            let nodes = |segment: &Segment| -> Vec<(i64, i32)> {
+                segment.dict.get(term).expect("").nodes().map(|node| (node.doc_id, node.freq)).collect()
+            };
+            assert_eq!(nodes(&imported), nodes(&segment), "term {:?}", term);
+        }
+        assert_eq!(imported.docs[&3].fields["title"], vec!["Rust".to_string()]);
+        assert_eq!(imported.docs[&7].text, segment.docs[&7].text);
+
+        let compressed = fs::metadata(path).expect("").len();
+        let mut gunzipped = Vec::new();
+        GzDecoder::new(File::open(path).expect("")).read_to_end(&mut gunzipped).expect("");
+        assert!(compressed < gunzipped.len() as u64, "{} >= {}", compressed, gunzipped.len());
+        assert_sorted(&imported);
+    }
+
+    #[test]
+    fn import_gz_malformed_postings_fails() {
+        let path = temp_index_path("import_gz_malformed");
+        let snapshot = r#"{"documents":[],"doc_lengths":{},"total_tokens_indexed":0,
+                           "postings":{"rust":[[1,2147483647],[1,2147483647]]}}"#;
+        let mut encoder = GzEncoder::new(File::create(&path).expect(""), Compression::default());
+        encoder.write_all(snapshot.as_bytes()).expect("");
+        encoder.finish().expect("");
+        assert!(matches!(import_gz(&path), Err(e) if e.kind() == std::io::ErrorKind::InvalidData));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn delete_where_success() {
+        let documents: Vec<Document> = (0..10)
+            .map(|id| Document::new(id, &format!("shared {}", if id % 2 == 0 { "even" } else { "odd" })))
+            .collect();
+        let mut segment = index_documents(documents).expect("");
+
+        assert_eq!(delete_where(&mut segment, |doc| doc.id % 2 == 0), 5);
+        assert_eq!(segment.total_docs, 5);
+        let mut ids: Vec<i64> = search(&segment, "shared".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3, 5, 7, 9]);
+        assert!(!term_exists(&segment, "even"));
+        assert_eq!(delete_where(&mut segment, |doc| doc.id > 100), 0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn reindex_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "Rust Search"),
+            Document::new(2, "rust compiler"),
+            Document::new(3, "Go"),
+        ]).expect("");
+        let ids = |segment: &Segment, query: &str| -> Vec<i64> {
+            let mut ids: Vec<i64> = search(segment, query.to_string(), 10).expect("").iter().map(|d| d.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&segment, "rust"), vec![2]);
+
+        let config = IndexConfig { tokenizer: Some(Arc::new(LowercaseTokenizer::new())), ..Default::default() };
+        let mut segment = reindex(segment, config).expect("");
+        assert_eq!(ids(&segment, "rust"), vec![1, 2]);
+        assert_eq!(ids(&segment, "search"), vec![1]);
+        assert!(!term_exists(&segment, "Rust"));
+
+        // later updates keep using the segment's tokenizer
+        add_document(&mut segment, Document::new(3, "RUST")).expect("");
+        assert_eq!(ids(&segment, "rust"), vec![1, 2, 3]);
+        delete_document(&mut segment, 1).expect("");
+        assert!(!term_exists(&segment, "search"));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_uses_index_tokenizer_success() {
+        let mut document = Document::new(2, "rust compiler");
+        document.fields.insert("lang".to_string(), vec!["Rust".to_string()]);
+        let segment = index_documents(vec![Document::new(1, "Rust Search"), document]).expect("");
+        let config = IndexConfig { tokenizer: Some(Arc::new(LowercaseTokenizer::new())), ..Default::default() };
+        let segment = reindex(segment, config).expect("");
+        let ids = |query: &str| -> Vec<i64> {
+            let mut ids: Vec<i64> = search(&segment, query.to_string(), 10).expect("").iter().map(|d| d.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids("Rust"), vec![1, 2]);
+        assert_eq!(ids("RUST sEaRcH"), vec![1]);
+        assert_eq!(ids("lang:RUST"), vec![2]);
+
+        let flat = flatten(segment.clone());
+        let mut flat_ids: Vec<i64> = search_flat(&flat, "Rust".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+        flat_ids.sort();
+        assert_eq!(flat_ids, vec![1, 2]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn explain_plan_uses_index_tokenizer_success() {
+        let segment = index_documents(vec![Document::new(1, "Hello World"), Document::new(2, "hello rust")]).expect("");
+        let config = IndexConfig { tokenizer: Some(Arc::new(LowercaseTokenizer::new())), ..Default::default() };
+        let segment = reindex(segment, config).expect("");
+
+        let plan = explain_plan(&segment, "Hello");
+        assert_eq!(plan.terms, vec![("hello".to_string(), 2)]);
+        assert_eq!(plan.expected_candidates, search(&segment, "Hello".to_string(), 10).expect("").len());
+        assert_eq!(expand_query_by_cooccurrence(&segment, "HELLO", 1), vec!["rust".to_string()]);
+        let ids: Vec<i64> = search_phrase(&segment, "HELLO world", 0, 10).iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![1]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn estimate_index_size_success() {
+        let mut state: u64 = 7;
+        let documents: Vec<Document> = (0..1000)
+            .map(|id| {
+                let words: Vec<String> = (0..20)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        format!("w{}", (state % 3000) * (state % 3000) / 3000)
+                    })
+                    .collect();
+                Document::new(id, &words.join(" "))
+            })
+            .collect();
+        let config = IndexConfig::default();
+        let estimate = estimate_index_size(&documents, &config);
+
+        let actual_doc_bytes: usize = documents.iter().map(document_size).sum();
+        let segment = index_documents_with_config(documents, &config).expect("");
+        let actual_posting_bytes: usize = segment.dict.terms()
+            .map(|term| segment.dict.get(term).expect("").len() * POSTING_NODE_SIZE)
+            .sum();
+        let within = |estimate: usize, actual: usize| {
+            assert!(estimate.abs_diff(actual) * 2 <= actual, "estimated {}, actual {}", estimate, actual);
+        };
+        within(estimate.estimated_term_count, segment.dict.len());
+        within(estimate.estimated_posting_list_bytes, actual_posting_bytes);
+        within(estimate.estimated_doc_store_bytes, actual_doc_bytes);
+        assert_eq!(estimate_index_size(&[], &config).estimated_term_count, 0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn index_deduplicate_tokens_success() {
+        let config = IndexConfig { deduplicate_tokens: true, ..Default::default() };
+        let segment = index_documents_with_config(vec![Document::new(1, "hello hello world")], &config).expect("");
+        assert_eq!(segment.dict.get("hello").expect("").get(1).expect("").freq, 1);
+        assert_eq!(segment.dict.get("world").expect("").get(1).expect("").freq, 1);
+
+        let segment = index_documents(vec![Document::new(1, "hello hello world")]).expect("");
+        assert_eq!(segment.dict.get("hello").expect("").get(1).expect("").freq, 2);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn iter_doc_freq_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "rust rust search"),
+            Document::new(2, "rust"),
+            Document::new(3, "search engine"),
+        ]).expect("");
+        for term in iter_terms(&segment) {
+            let posting = segment.dict.get(term).expect("");
+            let mut iterator = Iter::new(&posting).expect("");
+            let precomputed = iterator.doc_freq;
+            let mut postings = 1;
+            while iterator.advance() {
+                postings += 1;
+            }
+            let containing = segment.docs.values()
+                .filter(|doc| doc.text.split_whitespace().any(|word| word == term))
+                .count();
+            assert_eq!(precomputed, postings, "term {:?}", term);
+            assert_eq!(precomputed, containing, "term {:?}", term);
+        }
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_too_many_query_terms_fails() {
+        let segment = index_documents(vec![Document::new(1, "word0 word1")]).expect("");
+        let query: Vec<String> = (0..20).map(|i| format!("word{}", i)).collect();
+        let config = SearchConfig { max_query_terms: 10, ..SearchConfig::default() };
+        assert!(matches!(search_with_config(&segment, query.join(" "), 10, &config),
+                         Err(SearchError::TooManyQueryTerms { count: 20, max: 10 })));
+        assert!(search_with_config(&segment, query[..10].join(" "), 10, &config).is_ok());
+        assert!(matches!(search(&segment, format!("{} {}", query.join(" "), "extra"), 10),
+                         Err(SearchError::TooManyQueryTerms { count: 21, max: DEFAULT_MAX_QUERY_TERMS })));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn tied_scores_order_success() {
+        let documents: Vec<Document> = [7, 3, 12, 0, 5].iter().map(|&id| Document::new(id, "same words")).collect();
+        let segment = index_documents(documents).expect("");
+        let fuzzy = |segment: &Segment| -> Vec<i64> {
+            search_fuzzy(segment, "same", 1, 0.0, 10).expect("").iter().map(|d| d.id).collect()
+        };
+        assert_eq!(fuzzy(&segment), vec![0, 3, 5, 7, 12]);
+        assert_eq!(fuzzy(&segment.clone()), fuzzy(&segment));
+
+        let ids: Vec<i64> = search(&segment, "same".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![0, 3, 5, 7, 12]);
+        let mut heap: BinaryHeap<TopKDoc> = [4, 1, 9].iter().map(|&id| TopKDoc { id, score: F32(1.0) }).collect();
+        heap.push(TopKDoc { id: 6, score: F32(2.0) });
+        let popped: Vec<i64> = std::iter::from_fn(|| heap.pop()).map(|d| d.id).collect();
+        assert_eq!(popped, vec![6, 1, 4, 9]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn segment_diff_success() {
+        let old = index_documents(vec![Document::new(1, "rust search"), Document::new(2, "go")]).expect("");
+        let mut new = old.clone();
+        assert!(diff(&old, &new).is_empty());
+
+        add_document(&mut new, Document::new(3, "rust compiler")).expect("");
+        assert_eq!(diff(&old, &new), SegmentDiff {
+            added_docs: vec![3],
+            deleted_docs: vec![],
+            modified_terms: vec!["compiler".to_string(), "rust".to_string()],
+        });
+
+        delete_document(&mut new, 2).expect("");
+        let changes = diff(&old, &new);
+        assert_eq!(changes.deleted_docs, vec![2]);
+        assert_eq!(changes.modified_terms, vec!["compiler".to_string(), "go".to_string(), "rust".to_string()]);
+        assert_sorted(&new);
+    }
+
+    #[test]
+    fn word_boundary_tokenizer_success() {
+        let words = |tokenizer: &WordBoundaryTokenizer, text: &str| -> Vec<String> {
+            tokenizer.tokenize_with_positions(text).into_iter().map(|(token, _)| token).collect()
+        };
+        let keep = WordBoundaryTokenizer::default();
+        let split = WordBoundaryTokenizer { split_hyphens: true, split_apostrophes: true };
+
+        assert_eq!(words(&keep, "a state-of-the-art engine"), vec!["a", "state-of-the-art", "engine"]);
+        assert_eq!(words(&split, "a state-of-the-art engine"), vec!["a", "state", "of", "the", "art", "engine"]);
+        assert_eq!(words(&keep, "trailing- -leading"), vec!["trailing", "leading"]);
+        assert_eq!(words(&keep, "I can't, you won\u{2019}t."), vec!["I", "can't", "you", "won\u{2019}t"]);
+        assert_eq!(words(&split, "I can't, you won\u{2019}t."), vec!["I", "can", "t", "you", "won", "t"]);
+        assert_eq!(words(&keep, "pi is 3.14, not 1,000 (v2)"), vec!["pi", "is", "3.14", "not", "1,000", "v2"]);
+        assert_eq!(split.tokenize_with_positions("well-known fact"),
+                   vec![("well".to_string(), 0), ("known".to_string(), 1), ("fact".to_string(), 2)]);
+        assert!(words(&keep, " -- !").is_empty());
+    }
+
+    #[test]
+    fn total_term_freq_success() {
+        let doc_1 = Document::new(1, "hello hello");
+        let doc_2 = Document::new(2, "hello world");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+
+        assert_eq!(total_term_freq(&segment, "hello"), 3);
+        assert_eq!(term_stats(&segment, "hello"), Some(TermStats { doc_freq: 2, total_term_freq: 3 }));
+        assert_eq!(total_term_freq(&segment, "world"), 1);
+        assert_eq!(total_term_freq(&segment, "missing"), 0);
+        assert!(term_stats(&segment, "missing").is_none());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn live_iter_skips_tombstones_success() {
+        let docs: Vec<Document> = (1..=4).map(|id| Document::new(id, "shared")).collect();
+        let mut segment = index_documents(docs).expect("");
+        let posting = segment.dict.get("shared").expect("");
+        let tombstones: HashSet<i64> = [2, 4].into_iter().collect();
+        assert_eq!(LiveIter::new(&posting, &tombstones).map(|node| node.doc_id).collect::<Vec<i64>>(), vec![1, 3]);
+        assert_eq!(LiveIter::new(&posting, &HashSet::new()).count(), 4);
+
+        delete_document(&mut segment, 3).expect("");
+        let deleted: HashSet<i64> = [3].into_iter().collect();
+        assert!(LiveIter::new(&posting, &deleted).all(|node| node.doc_id != 3));
+        assert!(!segment.dict.get("shared").expect("").contains(3));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_source_filter_success() {
+        let docs: Vec<Document> = (1..=4)
+            .map(|id| {
+                let mut doc = Document::new(id, "federated search");
+                doc.source = Some(if id % 2 == 0 { "source_A" } else { "source_B" }.to_string());
+                doc
+            })
+            .chain(std::iter::once(Document::new(5, "federated search")))
+            .collect();
+        let segment = index_documents(docs).expect("");
+        let config = SearchConfig { source_filter: Some("source_A".to_string()), ..SearchConfig::default() };
+
+        let mut ids: Vec<i64> = search_with_config(&segment, "search".to_string(), 10, &config).expect("")
+            .iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![2, 4]);
+        let flat = flatten(segment.clone());
+        let mut flat_ids: Vec<i64> = search_flat_with_config(&flat, "search".to_string(), 10, &config).expect("")
+            .iter().map(|d| d.id).collect();
+        flat_ids.sort();
+        assert_eq!(flat_ids, vec![2, 4]);
+        assert_eq!(search(&segment, "search".to_string(), 10).expect("").len(), 5);
+
+        let dir = tempfile::tempdir().expect("");
+        let path = dir.path().to_str().expect("");
+        flush_to_disk(&segment, path).expect("");
+        let loaded = load_from_disk(path).expect("");
+        assert_eq!(loaded.docs[&2].source.as_deref(), Some("source_A"));
+        assert_eq!(loaded.docs[&5].source, None);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn search_min_score_success() {
+        let docs: Vec<Document> = (1..=6)
+            .map(|id| Document::new(id, &vec!["rust"; id as usize].join(" ")))
+            .chain((7..=10).map(|id| Document::new(id, "other")))
+            .collect();
+        let segment = index_documents(docs).expect("");
+        let all = search(&segment, "rust".to_string(), 10).expect("");
+        assert!(all.iter().any(|d| d.score.0 < 1.5) && all.iter().any(|d| d.score.0 >= 1.5));
+
+        let config = SearchConfig { min_score: Some(1.5), ..SearchConfig::default() };
+        let found = search_with_config(&segment, "rust".to_string(), 10, &config).expect("");
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|d| d.score.0 >= 1.5));
+        let mut expected: Vec<i64> = all.iter().filter(|d| d.score.0 >= 1.5).map(|d| d.id).collect();
+        let mut ids: Vec<i64> = found.iter().map(|d| d.id).collect();
+        expected.sort();
+        ids.sort();
+        assert_eq!(ids, expected);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn group_results_success() {
+        let brands = ["acme", "globex", "acme", "initech", "globex", "acme"];
+        let mut docs: Vec<Document> = brands.iter().zip(1..)
+            .map(|(brand, id)| {
+                let mut doc = Document::new(id, &vec!["shoe"; id as usize].join(" "));
+                doc.fields.insert("brand".to_string(), vec![brand.to_string()]);
+                doc
+            })
+            .collect();
+        docs.push(Document::new(7, "shoe"));
+        docs.push(Document::new(8, "filler"));
+        let segment = index_documents(docs).expect("");
+        let results = search(&segment, "shoe".to_string(), 10).expect("");
+
+        let groups = group_results(&segment, results, "brand");
+        assert_eq!(groups.keys().collect::<Vec<&String>>(), vec!["acme", "globex", "initech"]);
+        for (brand, hits) in &groups {
+            assert!(hits.iter().all(|d| segment.docs[&d.id].fields["brand"][0] == *brand));
+            assert!(hits.windows(2).all(|pair| pair[0].score.0 >= pair[1].score.0));
+        }
+        assert_eq!(groups["acme"].iter().map(|d| d.id).collect::<Vec<i64>>(), vec![6, 3, 1]);
+        assert_eq!(groups.values().map(|hits| hits.len()).sum::<usize>(), 6);
+        assert!(group_results(&segment, Vec::new(), "brand").is_empty());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn tracking_tokenizer_vocabulary_success() {
+        let mut tokenizer = TrackingTokenizer::new(NaiveTokenizer::new());
+        assert_eq!(tokenizer.vocabulary_size(), 0);
+        for text in ["hello world", "hello again", "world of words", ""] {
+            tokenizer.tokenize_with_positions(text);
+        }
+        assert_eq!(tokenizer.vocabulary_size(), 5);
+        assert_eq!(NaiveTokenizer::new().vocabulary_size(), 0);
+    }
+
+    #[test]
+    fn compress_document_store_success() {
+        let docs: Vec<Document> = (0..50)
+            .map(|id| Document::new(id, &format!("doc{} {}", id, "the quick brown fox jumps over the lazy dog ".repeat(20))))
+            .collect();
+        let plain = index_documents(docs.clone()).expect("");
+        let config = IndexConfig { compress_document_store: true, ..Default::default() };
+        let mut compressed = index_documents_with_config(docs.clone(), &config).expect("");
+
+        for doc in &docs {
+            assert_eq!(compressed.document(doc.id).expect("").text, doc.text);
+            assert!(compressed.docs[&doc.id].text.is_empty());
+        }
+        let plain_bytes: usize = plain.docs.values().map(|doc| doc.text.len()).sum();
+        let compressed_bytes: usize = compressed.compressed_texts.as_ref().expect("").values().map(|b| b.len()).sum();
+        assert!(compressed_bytes * 4 < plain_bytes, "{} vs {}", compressed_bytes, plain_bytes);
+
+        let ids = |segment: &Segment, query: &str| -> Vec<i64> {
+            let mut ids: Vec<i64> = search(segment, query.to_string(), 100).expect("").iter().map(|d| d.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&compressed, "fox"), ids(&plain, "fox"));
+        delete_document(&mut compressed, 7).expect("");
+        assert!(compressed.dict.get("doc7").is_none());
+        add_document(&mut compressed, Document::new(7, "a new fox")).expect("");
+        assert_eq!(compressed.document(7).expect("").text, "a new fox");
+        assert_eq!(ids(&compressed, "new"), vec![7]);
+        let reindexed = reindex(compressed, IndexConfig::default()).expect("");
+        assert!(reindexed.compressed_texts.is_none());
+        assert_eq!(reindexed.docs[&3].text, docs[3].text);
+        assert_sorted(&reindexed);
+    }
+
+    #[test]
+    fn tokenize_n_best_success() {
+        let tokenizer = NBestTokenizer::new(&["東京", "京都", "東", "都", "hot", "dog", "hotdog"], 2);
+        let segmentations = tokenize_n_best("東京都", 2, &tokenizer.dict);
+        assert_eq!(segmentations.len(), 2);
+        assert_ne!(segmentations[0], segmentations[1]);
+        assert!(segmentations.iter().all(|tokens| tokens.len() == 2 && tokens.concat() == "東京都"));
+        assert!(segmentations.contains(&vec!["東京".to_string(), "都".to_string()]));
+        assert!(segmentations.contains(&vec!["東".to_string(), "京都".to_string()]));
+
+        assert_eq!(tokenize_n_best("hotdog", 2, &tokenizer.dict),
+                   vec![vec!["hotdog".to_string()], vec!["hot".to_string(), "dog".to_string()]]);
+        assert_eq!(tokenize_n_best("hotx", 1, &tokenizer.dict), vec![vec!["hot".to_string(), "x".to_string()]]);
+        assert!(tokenize_n_best("", 2, &tokenizer.dict).is_empty());
+        assert_eq!(tokenize_n_best("京", 5, &tokenizer.dict), vec![vec!["京".to_string()]]);
+
+        let config = IndexConfig { tokenizer: Some(Arc::new(tokenizer)), ..Default::default() };
+        let segment = index_documents_with_config(vec![Document::new(1, "東京都 hotdog"),
+                                                       Document::new(2, "京都")], &config).expect("");
+        for term in ["東京", "都", "東", "京都", "hotdog", "hot", "dog"] {
+            assert!(segment.dict.get(term).expect(term).contains(1), "{}", term);
+        }
+        let mut ids: Vec<i64> = search(&segment, "京都".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn doc_id_generators_success() {
+        let docs = vec![Document::new(0, "first"), Document::new(0, "second"), Document::new(0, "third")];
+        let config = IndexConfig { id_generator: Some(Box::new(SequentialIdGenerator::new(10))), ..Default::default() };
+        let mut segment = index_documents_with_config(docs.clone(), &config).expect("");
+        let ids = |segment: &Segment| -> Vec<(i64, String)> {
+            let mut ids: Vec<(i64, String)> = segment.docs.values().map(|doc| (doc.id, doc.text.clone())).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&segment), vec![(10, "first".to_string()), (11, "second".to_string()), (12, "third".to_string())]);
+        add_document_with_config(&mut segment, Document::new(0, "fourth"), &config).expect("");
+        assert_eq!(search(&segment, "fourth".to_string(), 10).expect("")[0].id, 13);
+
+        let hasher = ContentHashIdGenerator;
+        assert_eq!(hasher.generate(&Document::new(1, "same text")), hasher.generate(&Document::new(2, "same text")));
+        assert_ne!(hasher.generate(&docs[0]), hasher.generate(&docs[1]));
+        let config = IndexConfig { id_generator: Some(Box::new(ContentHashIdGenerator)), ..Default::default() };
+        let mut hashed = index_documents_with_config(docs.clone(), &config).expect("");
+        assert_eq!(search(&hashed, "second".to_string(), 10).expect("")[0].id, hasher.generate(&docs[1]));
+        add_document_with_config(&mut hashed, Document::new(99, "first"), &config).expect("");
+        assert_eq!(hashed.total_docs, 3);
+        let dupes = vec![Document::new(1, "same"), Document::new(2, "same")];
+        assert!(matches!(index_documents_with_config(dupes, &config), Err(SearchError::DuplicateDocumentId(_))));
+        assert_sorted(&segment);
+        assert_sorted(&hashed);
+    }
+
+    #[test]
+    fn apply_tx_success() {
+        let mut segment = index_documents(vec![Document::new(1, "old words"), Document::new(2, "other words"),
+                                               Document::new(3, "filler")]).expect("");
+        let tx = PostingListTx {
+            removes: vec![1],
+            adds: vec![("new".to_string(), 1, 2), ("words".to_string(), 1, 1)],
+        };
+        apply_tx(&mut segment, tx).expect("");
+        assert!(segment.dict.get("old").is_none());
+        assert_eq!(term_freq(&segment, "new", 1), 2);
+        assert_eq!(segment.doc_lengths[&1], 3);
+        let mut ids: Vec<i64> = search(&segment, "words".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(verify_integrity(&segment).is_ok());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn apply_tx_rolls_back_on_failure() {
+        let mut segment = index_documents(vec![Document::new(1, "old words"), Document::new(2, "other words"),
+                                               Document::new(3, "filler")]).expect("");
+        let before = segment.clone();
+        let state = |segment: &Segment| -> Vec<(String, Vec<(i64, i32)>)> {
+            segment.dict.terms()
+                .map(|t| (t.to_string(), segment.dict.get(t).expect("").nodes().map(|n| (n.doc_id, n.freq)).collect()))
+                .collect()
+        };
+        // fails on the third add, after the remove and two adds went through
+        let tx = PostingListTx {
+            removes: vec![1],
+            adds: vec![("new".to_string(), 1, 2), ("words".to_string(), 2, 1), ("ghost".to_string(), 99, 1)],
+        };
+        assert!(matches!(apply_tx(&mut segment, tx), Err(SearchError::DocumentNotFound(99))));
+        assert_eq!(state(&segment), state(&before));
+        assert_eq!(segment.doc_lengths, before.doc_lengths);
+        assert_eq!(segment.total_tokens_indexed, before.total_tokens_indexed);
+
+        let tx = PostingListTx { removes: vec![2], adds: vec![("bad".to_string(), 3, 0)] };
+        assert!(matches!(apply_tx(&mut segment, tx), Err(SearchError::InvalidFrequency { freq: 0, .. })));
+        assert_eq!(state(&segment), state(&before));
+        let adds = vec![("over".to_string(), 1, i32::MAX), ("over".to_string(), 1, 1)];
+        let tx = PostingListTx { adds, removes: Vec::new() };
+        assert!(matches!(apply_tx(&mut segment, tx), Err(SearchError::InvalidFrequency { freq: 1, .. })));
+        assert_eq!(state(&segment), state(&before));
+        let tx = PostingListTx { removes: vec![1, 42], adds: Vec::new() };
+        assert!(matches!(apply_tx(&mut segment, tx), Err(SearchError::DocumentNotFound(42))));
+        assert_eq!(state(&segment), state(&before));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn max_term_score_fraction_success() {
+        let mut docs: Vec<Document> = (2..=20).map(|id| Document::new(id, "common filler words here")).collect();
+        docs.push(Document::new(1, "rare common"));
+        let segment = index_documents(docs).expect("");
+        let rare = doc_term_score(&segment, "rare", 1).expect("");
+        let common = doc_term_score(&segment, "common", 1).expect("");
+        assert!(rare > common);
+
+        let plain = search(&segment, "rare common".to_string(), 10).expect("");
+        assert_eq!(plain[0].score.0, rare + common);
+        let config = SearchConfig { max_term_score_fraction: Some(0.5), ..SearchConfig::default() };
+        let capped = search_with_config(&segment, "rare common".to_string(), 10, &config).expect("");
+        assert_eq!(capped[0].id, 1);
+        assert_eq!(capped[0].score.0, 2.0 * common);
+
+        let mut scores = vec![rare, common];
+        cap_term_scores(&mut scores, 0.5);
+        let total: f32 = scores.iter().sum();
+        assert!(scores.iter().all(|&score| score <= 0.5 * total));
+
+        let mut scores = vec![1.0, 2.0, 6.0];
+        cap_term_scores(&mut scores, 0.5);
+        assert_eq!(scores, vec![1.0, 2.0, 3.0]);
+        let mut scores = vec![1.0, 1.0, 1.0];
+        cap_term_scores(&mut scores, 0.5);
+        assert_eq!(scores, vec![1.0, 1.0, 1.0]);
+        let mut scores = vec![4.0];
+        cap_term_scores(&mut scores, 0.5);
+        assert_eq!(scores, vec![4.0]);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn parse_query_success() {
+        assert_eq!(parse_query("  hello\tworld ").expect(""), vec!["hello", "world"]);
+        assert!(matches!(parse_query(""), Err(SearchError::EmptyQuery)));
+        assert!(matches!(parse_query(" \n "), Err(SearchError::EmptyQuery)));
+    }
+
+    #[test]
+    fn search_unknown_term_returns_nothing() {
+        let segment = index_documents(vec![Document::new(1, "hello")]).expect("");
+        let found_docs = search(&segment, "missing".to_string(), 2).expect("");
+        assert!(found_docs.is_empty());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn segment_format_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test there");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+        assert_eq!(format!("{:?}", segment),
+                   "Segment { total_docs: 2, unique_terms: 6, first_terms: [\"hello\", \"is\", \"second\", \"test\", \"there\"] }");
+        assert_eq!(format!("{}", segment), "Segment with 2 documents and 6 unique terms");
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn clone_segment_is_independent() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+        let mut cloned = segment.clone();
+
+        let doc_3 = Document::new(3, "cloned");
+        cloned.docs.insert(3, Arc::new(doc_3));
+        cloned.dict.insert("cloned", PostingList::from_iter([PostingNode { doc_id: 3, freq: 1 }]));
+        cloned.dict.insert("test", PostingList::from_iter([PostingNode { doc_id: 3, freq: 5 }]));
+
+        assert_eq!(cloned.docs.len(), 3);
+        assert_eq!(cloned.dict.len(), segment.dict.len() + 1);
+        assert_eq!(segment.docs.len(), 2);
+        assert!(segment.dict.get("cloned").is_none());
+        let posting = segment.dict.get("test").expect("");
+        assert_eq!(posting.len(), 2);
+        assert_eq!(posting.get(2).expect("").freq, 2);
+        assert_sorted(&segment);
+        assert_sorted(&cloned);
+    }
+
+    #[test]
+    fn total_docs_tracks_mutations() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let mut segment = index_documents(vec![doc_1, doc_2]).expect("");
+        assert_eq!(segment.total_docs, 2);
+        assert_eq!(segment.total_docs, segment.docs.len());
+
+        add_document(&mut segment, Document::new(3, "hello third")).expect("");
+        assert_eq!(segment.total_docs, 3);
+        assert_eq!(segment.total_docs, segment.docs.len());
+
+        add_document(&mut segment, Document::new(3, "replaced")).expect("");
+        assert_eq!(segment.total_docs, 3);
+        assert_eq!(segment.total_docs, segment.docs.len());
+        assert!(segment.dict.get("third").is_none());
+
+        delete_document(&mut segment, 1).expect("");
+        assert_eq!(segment.total_docs, 2);
+        assert_eq!(segment.total_docs, segment.docs.len());
+        assert!(segment.dict.get("this").is_none());
+        assert_eq!(segment.dict.get("test").expect("").len(), 1);
+
+        assert!(matches!(delete_document(&mut segment, 1), Err(SearchError::DocumentNotFound(1))));
+        assert_eq!(segment.total_docs, segment.docs.len());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn total_tokens_indexed_success() {
+        let mut segment = index_documents(vec![Document::new(1, "one two three four five")]).expect("");
+        assert_eq!(segment_stats(&segment).total_tokens_indexed, 5);
+        add_document(&mut segment, Document::new(2, "six seven eight")).expect("");
+        let stats = segment_stats(&segment);
+        assert_eq!(stats.total_tokens_indexed, 8);
+        assert_eq!(stats.total_docs, 2);
+        assert_eq!(stats.unique_terms, 8);
+        assert_eq!(stats.avg_doc_len, 4.0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn idf_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let doc_3 = Document::new(3, "hello");
+        let doc_4 = Document::new(4, "other");
+        let segment = index_documents(vec![doc_1, doc_2, doc_3, doc_4]).expect("");
+        assert_eq!(idf(&segment, "hello"), (4.0f32 / 3.0).log2());
+        assert_eq!(idf(&segment, "test"), 1.0);
+        assert_eq!(idf(&segment, "second"), 2.0);
+        assert_eq!(idf(&segment, "missing"), 0.0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn term_freq_success() {
+        let doc_1 = Document::new(1, "hello hello world");
+        let doc_2 = Document::new(2, "world");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+        assert_eq!(term_freq(&segment, "hello", 1), 2);
+        assert_eq!(term_freq(&segment, "world", 1), 1);
+        assert_eq!(term_freq(&segment, "hello", 2), 0);
+        assert_eq!(term_freq(&segment, "missing", 1), 0);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn term_exists_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+        for term in ["hello", "test", "tes", "testing", "second", "", "missing"] {
+            assert_eq!(term_exists(&segment, term), segment.dict.get(term).is_some(), "term {}", term);
+        }
+        assert!(term_exists(&segment, "test"));
+        assert!(!term_exists(&segment, "tes"));
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn iter_terms_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let doc_2 = Document::new(2, "hello second test test");
+        let segment = index_documents(vec![doc_1, doc_2]).expect("");
+        let terms: Vec<&str> = iter_terms(&segment).collect();
+        let mut expected = vec!["this", "test", "second", "is", "hello"];
+        expected.sort();
+        assert_eq!(terms, expected);
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn delete_by_prefix_success() {
+        let doc_1 = Document::new(1, "spam123 hello");
+        let doc_2 = Document::new(2, "spam456 not-spam");
+        let mut segment = index_documents(vec![doc_1, doc_2]).expect("");
+        delete_by_prefix(&mut segment, "spam");
+
+        assert!(segment.dict.get("spam123").is_none());
+        assert!(segment.dict.get("spam456").is_none());
+        assert!(segment.dict.get("not-spam").is_some());
+        assert!(segment.dict.get("hello").is_some());
+        assert_eq!(segment.dict.len(), 2);
+        assert!(search(&segment, "spam123".to_string(), 2).expect("").is_empty());
+        assert_sorted(&segment);
+    }
+
+    #[test]
+    fn flush_and_load_success() {
+        let doc_1 = Document::new(1, "hello this is test");
+        let mut doc_2 = Document::new(2, "hello second test test");
+        doc_2.fields.insert("tag".to_string(), vec!["a".to_string(), "b".to_string()]);
+        let docs = vec![doc_1, doc_2];
+        let segment = index_documents(docs).expect("");
+        let path = temp_index_path("flush_and_load");
+        flush_to_disk(&segment, &path).expect("");
+
+        let loaded = load_from_disk(&path).expect("");
+        assert_eq!(loaded.dict.len(), segment.dict.len());
+        assert_eq!(loaded.docs[&2].text, "hello second test test");
+        assert_eq!(loaded.docs[&2].fields["tag"], vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(loaded.doc_lengths[&2], 6);
+        let posting = loaded.dict.get("test").expect("");
+        assert_eq!(posting.get(2).expect("").doc_id, 2);
+        assert_eq!(posting.get(2).expect("").freq, 2);
+        assert_sorted(&segment);
+        assert_sorted(&loaded);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn load_incompatible_version_fails() {
+        let segment = index_documents(vec![Document::new(1, "hello")]).expect("");
+        let path = temp_index_path("incompatible_version");
+        flush_to_disk(&segment, &path).expect("");
+
+        let term_dict_path = format!("{}/{}", path, TERM_DICT_FILE_NAME);
+        let mut bytes = fs::read(&term_dict_path).expect("");
+        bytes[0] = 7;
+        fs::write(&term_dict_path, bytes).expect("");
+
+        match load_from_disk(&path) {
+            Err(SearchError::IncompatibleVersion { found, expected }) => {
+                assert_eq!(found, 7);
+                assert_eq!(expected, VERSION);
+            }
+            _ => panic!("expected IncompatibleVersion"),
+        }
+        assert_sorted(&segment);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn load_malformed_postings_fails() {
+        let segment = index_documents(vec![Document::new(1, "hello"), Document::new(2, "hello")]).expect("");
+        let path = temp_index_path("malformed_postings");
+        let postings_path = format!("{}/{}", path, POSTING_LISTS_FILE_NAME);
+        let node = |doc_id: i64, freq: i32| -> Vec<u8> {
+            doc_id.to_le_bytes().into_iter().chain(freq.to_le_bytes()).collect()
+        };
+        for nodes in [[node(1, i32::MAX), node(1, i32::MAX)], [node(2, 1), node(1, 1)], [node(1, 1), node(2, 0)]] {
+            flush_to_disk(&segment, &path).expect("");
+            let mut bytes = VERSION.to_le_bytes().to_vec();
+            bytes.extend(nodes.concat());
+            fs::write(&postings_path, bytes).expect("");
+            assert!(matches!(load_from_disk(&path), Err(SearchError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData));
+        }
+        assert_sorted(&segment);
+        let _ = fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,72 @@
+//! Prometheus-style metrics, recorded through the `metrics` facade when the
+//! `metrics` feature is enabled. Installing an exporter is left to the caller.
+
+use std::time::Duration;
+
+pub const SEARCH_DURATION_SECONDS: &str = "danton_search_duration_seconds";
+pub const INDEX_DOCUMENTS_TOTAL: &str = "danton_index_documents_total";
+pub const INDEX_TOKENS_TOTAL: &str = "danton_index_tokens_total";
+pub const SEGMENT_DOCS_GAUGE: &str = "danton_segment_docs_gauge";
+pub const CACHE_HITS_TOTAL: &str = "danton_cache_hits_total";
+pub const CACHE_MISSES_TOTAL: &str = "danton_cache_misses_total";
+
+pub(crate) fn record_search(elapsed: Duration) {
+    ::metrics::histogram!(SEARCH_DURATION_SECONDS).record(elapsed.as_secs_f64());
+}
+
+/// `indexed` documents holding `tokens` tokens were added to a segment that now
+/// holds `segment_docs`.
+pub(crate) fn record_indexed(indexed: usize, tokens: u64, segment_docs: usize) {
+    ::metrics::counter!(INDEX_DOCUMENTS_TOTAL).increment(indexed as u64);
+    ::metrics::counter!(INDEX_TOKENS_TOTAL).increment(tokens);
+    ::metrics::gauge!(SEGMENT_DOCS_GAUGE).set(segment_docs as f64);
+}
+
+pub(crate) fn record_cache_lookup(hit: bool) {
+    if hit {
+        ::metrics::counter!(CACHE_HITS_TOTAL).increment(1);
+    } else {
+        ::metrics::counter!(CACHE_MISSES_TOTAL).increment(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    use crate::metrics::{CACHE_HITS_TOTAL, CACHE_MISSES_TOTAL, INDEX_DOCUMENTS_TOTAL, INDEX_TOKENS_TOTAL,
+                         SEARCH_DURATION_SECONDS, SEGMENT_DOCS_GAUGE};
+    use crate::{add_document, batch_search, Document, index_documents, search};
+
+    #[test]
+    fn metrics_recorded_success() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mut segment = index_documents(vec![Document::new(1, "hello world"), Document::new(2, "hello")])
+                .expect("");
+            add_document(&mut segment, Document::new(3, "world")).expect("");
+            search(&segment, "hello".to_string(), 10).expect("");
+            search(&segment, "world".to_string(), 10).expect("");
+            batch_search(&segment, vec!["hello world".to_string(), "hello".to_string()], 10);
+        });
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let value = |kind: MetricKind, name: &str| {
+            snapshot.iter()
+                .find(|(key, _)| key.kind() == kind && key.key().name() == name)
+                .map(|(_, (_, _, value))| value)
+                .expect(name)
+        };
+        assert_eq!(value(MetricKind::Counter, INDEX_DOCUMENTS_TOTAL), &DebugValue::Counter(3));
+        assert_eq!(value(MetricKind::Counter, INDEX_TOKENS_TOTAL), &DebugValue::Counter(4));
+        assert_eq!(value(MetricKind::Gauge, SEGMENT_DOCS_GAUGE), &DebugValue::Gauge(3.0.into()));
+        assert_eq!(value(MetricKind::Counter, CACHE_HITS_TOTAL), &DebugValue::Counter(1));
+        assert_eq!(value(MetricKind::Counter, CACHE_MISSES_TOTAL), &DebugValue::Counter(2));
+        match value(MetricKind::Histogram, SEARCH_DURATION_SECONDS) {
+            DebugValue::Histogram(samples) => assert_eq!(samples.len(), 2),
+            other => panic!("expected a histogram, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::{Document, merge, search_with_config, SearchConfig, SearchError, Segment, TopKDoc};
+
+/// Which segments `MultiSegmentIndex::add_segment` drops.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentEvictionPolicy {
+    KeepAll,
+    /// Keep only the N most recently added segments.
+    KeepLatestN(usize),
+    /// Drop segments created longer ago than the duration.
+    EvictOlderThan(Duration),
+}
+
+/// A set of independently built segments searched as one index. Every segment
+/// scores its hits with its own statistics; the hits are then merged by score.
+pub struct MultiSegmentIndex {
+    /// Segments in the order they were added, oldest first.
+    pub segments: Vec<Segment>,
+    pub eviction_policy: SegmentEvictionPolicy,
+}
+
+impl MultiSegmentIndex {
+    pub fn new() -> Self {
+        MultiSegmentIndex::with_eviction_policy(SegmentEvictionPolicy::KeepAll)
+    }
+
+    pub fn with_eviction_policy(eviction_policy: SegmentEvictionPolicy) -> Self {
+        MultiSegmentIndex { segments: Vec::new(), eviction_policy }
+    }
+
+    /// Adds a segment, then evicts whatever the policy no longer keeps.
+    pub fn add_segment(&mut self, segment: Segment) {
+        self.segments.push(segment);
+        match self.eviction_policy {
+            SegmentEvictionPolicy::KeepAll => {}
+            SegmentEvictionPolicy::KeepLatestN(n) => {
+                if self.segments.len() > n {
+                    let evicted = self.segments.len() - n;
+                    self.segments.drain(..evicted);
+                }
+            }
+            SegmentEvictionPolicy::EvictOlderThan(max_age) => {
+                self.segments.retain(|segment| segment.created_at.elapsed() <= max_age);
+            }
+        }
+    }
+
+    pub fn search(&self, query: &str, size: i32) -> Result<Vec<TopKDoc>, SearchError> {
+        self.search_with_config(query, size, &SearchConfig::default())
+    }
+
+    pub fn search_with_config(&self, query: &str, size: i32,
+                              config: &SearchConfig) -> Result<Vec<TopKDoc>, SearchError> {
+        search_segments(self.segments.iter(), query, size, config)
+    }
+}
+
+/// Merges the `n` segments holding the fewest documents into one, which takes
+/// the place of the oldest of them. Ties go to the older segment. Does nothing
+/// unless at least two segments would be merged.
+pub fn compact_smallest_n(index: &mut MultiSegmentIndex, n: usize) {
+    let n = n.min(index.segments.len());
+    if n < 2 {
+        return;
+    }
+    let mut by_size: Vec<usize> = (0..index.segments.len()).collect();
+    by_size.sort_by_key(|&i| index.segments[i].docs.len());
+    let mut chosen: Vec<usize> = by_size[..n].to_vec();
+    chosen.sort();
+    let mut merged = Vec::with_capacity(n);
+    for &i in chosen.iter().rev() {
+        merged.push(index.segments.remove(i));
+    }
+    // removed newest first; merge expects them oldest first
+    merged.reverse();
+    index.segments.insert(chosen[0], merge(merged));
+}
+
+/// Splits documents into `num_shards` groups by `|doc_id| % num_shards`, each to be
+/// indexed as its own segment. Panics if `num_shards` is 0.
+pub fn shard_documents(documents: Vec<Document>, num_shards: usize) -> Vec<Vec<Document>> {
+    assert!(num_shards > 0, "num_shards must be positive");
+    let mut shards: Vec<Vec<Document>> = (0..num_shards).map(|_| Vec::new()).collect();
+    for document in documents {
+        let shard = (document.id.unsigned_abs() % num_shards as u64) as usize;
+        shards[shard].push(document);
+    }
+    shards
+}
+
+/// Searches every segment and merges their hits by score. A document found in
+/// more than one segment is kept once, with its best score.
+pub(crate) fn search_segments<'a>(segments: impl Iterator<Item = &'a Segment>, query: &str, size: i32,
+                                  config: &SearchConfig) -> Result<Vec<TopKDoc>, SearchError> {
+    let mut result = Vec::new();
+    for segment in segments {
+        let mut hits = search_with_config(segment, query.to_string(), size, config)?;
+        if let Some(max_hits) = config.max_hits_per_segment {
+            hits.truncate(max_hits);
+        }
+        result.extend(hits);
+    }
+    result.sort_by(|a, b| b.cmp(a));
+    let mut seen = HashSet::new();
+    result.retain(|hit| seen.insert(hit.id));
+    result.truncate(size as usize);
+    Ok(result)
+}
+
+impl Default for MultiSegmentIndex {
+    fn default() -> Self {
+        MultiSegmentIndex::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::{assert_sorted, compact_smallest_n, Document, index_documents, MultiSegmentIndex, search,
+                SearchConfig, Segment, SegmentEvictionPolicy, shard_documents};
+
+    #[test]
+    fn search_across_segments_success() {
+        let mut index = MultiSegmentIndex::new();
+        index.add_segment(index_documents(vec![Document::new(1, "hello world"),
+                                               Document::new(2, "world")]).expect(""));
+        index.add_segment(index_documents(vec![Document::new(3, "hello hello"),
+                                               Document::new(4, "other")]).expect(""));
+
+        let mut ids: Vec<i64> = index.search("hello", 10).expect("").iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn search_across_segments_dedup_success() {
+        let mut index = MultiSegmentIndex::new();
+        index.add_segment(index_documents(vec![Document::new(1, "hello"), Document::new(2, "filler")]).expect(""));
+        index.add_segment(index_documents(vec![Document::new(1, "hello hello"), Document::new(3, "hello"),
+                                               Document::new(4, "filler")]).expect(""));
+
+        let found = index.search("hello", 10).expect("");
+        let mut ids: Vec<i64> = found.iter().map(|d| d.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 3]);
+        let best = index.segments.iter()
+            .map(|segment| search(segment, "hello".to_string(), 10).expect("").iter()
+                .find(|d| d.id == 1).expect("").score.0)
+            .fold(f32::MIN, f32::max);
+        assert_eq!(found.iter().find(|d| d.id == 1).expect("").score.0, best);
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn compact_smallest_n_success() {
+        let mut index = MultiSegmentIndex::new();
+        let mut next_id = 0;
+        for size in [8, 2, 5, 1, 3] {
+            let docs: Vec<Document> = (next_id..next_id + size)
+                .map(|id| Document::new(id, &format!("hello doc{}", id)))
+                .collect();
+            next_id += size;
+            index.add_segment(index_documents(docs).expect(""));
+        }
+        let before: Vec<Vec<i64>> = ["hello", "doc9", "doc15"].iter()
+            .map(|q| {
+                let mut ids: Vec<i64> = index.search(q, 100).expect("").iter().map(|d| d.id).collect();
+                ids.sort();
+                ids
+            })
+            .collect();
+
+        compact_smallest_n(&mut index, 3);
+        assert_eq!(index.segments.len(), 3);
+        assert_eq!(index.segments.iter().map(|s| s.docs.len()).collect::<Vec<usize>>(), vec![8, 6, 5]);
+        assert_eq!(index.segments.iter().map(|s| s.total_docs).sum::<usize>(), 19);
+        for (query, expected) in ["hello", "doc9", "doc15"].iter().zip(before) {
+            let mut ids: Vec<i64> = index.search(query, 100).expect("").iter().map(|d| d.id).collect();
+            ids.sort();
+            assert_eq!(ids, expected);
+        }
+        compact_smallest_n(&mut index, 1);
+        assert_eq!(index.segments.len(), 3);
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn merge_keeps_latest_version_success() {
+        let old = index_documents(vec![Document::new(1, "old text"), Document::new(2, "kept")]).expect("");
+        let new = index_documents(vec![Document::new(1, "new text"), Document::new(3, "added")]).expect("");
+        let merged = crate::merge(vec![old, new]);
+
+        assert!(merged.is_built());
+        assert_eq!(merged.total_docs, 3);
+        assert_eq!(merged.docs[&1].text, "new text");
+        assert!(search(&merged, "old".to_string(), 10).expect("").is_empty());
+        assert_eq!(search(&merged, "text".to_string(), 10).expect("").len(), 1);
+        assert_eq!(merged.total_tokens_indexed, 4);
+        assert_sorted(&merged);
+    }
+
+    #[test]
+    fn concurrent_search_success() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Segment>();
+        assert_send_sync::<MultiSegmentIndex>();
+
+        let mut index = MultiSegmentIndex::new();
+        for shard in shard_documents((0..200).map(|id| Document::new(id, &format!("hello word{}", id % 13))).collect(), 4) {
+            index.add_segment(index_documents(shard).expect(""));
+        }
+        let queries: Vec<String> = (0..13).map(|i| format!("hello word{}", i)).collect();
+        let expected: Vec<Vec<i64>> = queries.iter()
+            .map(|q| index.search(q, 50).expect("").iter().map(|d| d.id).collect())
+            .collect();
+
+        let index = Arc::new(index);
+        let expected = Arc::new(expected);
+        let threads: Vec<_> = (0..16)
+            .map(|t| {
+                let index = Arc::clone(&index);
+                let expected = Arc::clone(&expected);
+                let queries = queries.clone();
+                thread::spawn(move || {
+                    for round in 0..20 {
+                        let i = (t + round) % queries.len();
+                        let ids: Vec<i64> = index.search(&queries[i], 50).expect("").iter().map(|d| d.id).collect();
+                        assert_eq!(ids, expected[i]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("");
+        }
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn max_hits_per_segment_success() {
+        let large = (0..20).map(|id| Document::new(id, "hello large")).chain(vec![Document::new(99, "filler")]);
+        let small = vec![Document::new(100, "hello small"), Document::new(101, "filler")];
+        let mut index = MultiSegmentIndex::new();
+        index.add_segment(index_documents(large.collect()).expect(""));
+        index.add_segment(index_documents(small).expect(""));
+
+        assert_eq!(index.search("hello", 10).expect("").len(), 10);
+
+        let config = SearchConfig { max_hits_per_segment: Some(1), ..SearchConfig::default() };
+        let found_docs = index.search_with_config("hello", 10, &config).expect("");
+        assert_eq!(found_docs.len(), 2);
+        assert_eq!(found_docs.iter().filter(|d| d.id < 20).count(), 1);
+        assert_eq!(found_docs.iter().filter(|d| d.id == 100).count(), 1);
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn keep_latest_n_eviction_success() {
+        let mut index = MultiSegmentIndex::with_eviction_policy(SegmentEvictionPolicy::KeepLatestN(2));
+        for id in 1..=3 {
+            index.add_segment(index_documents(vec![Document::new(id, &format!("hello doc{}", id)),
+                                                   Document::new(id + 100, "filler")]).expect(""));
+        }
+        assert_eq!(index.segments.len(), 2);
+        assert!(index.search("doc1", 10).expect("").is_empty());
+        assert_eq!(index.search("doc2", 10).expect("")[0].id, 2);
+        assert_eq!(index.search("doc3", 10).expect("")[0].id, 3);
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn evict_older_than_success() {
+        let max_age = Duration::from_secs(60);
+        let mut index = MultiSegmentIndex::with_eviction_policy(SegmentEvictionPolicy::EvictOlderThan(max_age));
+        let mut old = index_documents(vec![Document::new(1, "old"), Document::new(2, "filler")]).expect("");
+        old.created_at = Instant::now() - Duration::from_secs(120);
+        index.add_segment(old);
+        assert!(index.segments.is_empty());
+
+        index.add_segment(index_documents(vec![Document::new(3, "new"), Document::new(4, "filler")]).expect(""));
+        assert_eq!(index.segments.len(), 1);
+        assert_eq!(index.search("new", 10).expect("")[0].id, 3);
+        index.segments.iter().for_each(assert_sorted);
+    }
+
+    #[test]
+    fn shard_documents_success() {
+        let documents: Vec<Document> = (-10..30)
+            .map(|id| Document::new(id, if id % 3 == 0 { "hello fizz" } else { "hello" }))
+            .collect();
+        let shards = shard_documents(documents.clone(), 4);
+        assert_eq!(shards.len(), 4);
+        for (i, shard) in shards.iter().enumerate() {
+            assert!(shard.iter().all(|doc| doc.id.unsigned_abs() as usize % 4 == i));
+        }
+        let mut ids: Vec<i64> = shards.iter().flatten().map(|doc| doc.id).collect();
+        ids.sort();
+        assert_eq!(ids, documents.iter().map(|doc| doc.id).collect::<Vec<i64>>());
+
+        let mut index = MultiSegmentIndex::new();
+        for shard in shards {
+            index.add_segment(index_documents(shard).expect(""));
+        }
+        let single = index_documents(documents).expect("");
+        let mut expected: Vec<i64> = search(&single, "fizz".to_string(), 100).expect("").iter().map(|d| d.id).collect();
+        let mut found: Vec<i64> = index.search("fizz", 100).expect("").iter().map(|d| d.id).collect();
+        expected.sort();
+        found.sort();
+        assert_eq!(found, expected);
+        index.segments.iter().for_each(assert_sorted);
+        assert_sorted(&single);
+    }
+}
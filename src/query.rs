@@ -0,0 +1,322 @@
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+
+use crate::{doc_term_score, document_tokens, edit_distance, F32, NaiveTokenizer, query_terms, Segment, Tokenizer,
+            TopKDoc};
+
+/// A query that can be evaluated one document at a time, so different query
+/// types can be passed around and combined behind `&dyn Query`.
+pub trait Query: Send + Sync {
+    fn matches(&self, segment: &Segment, doc_id: i64) -> bool;
+    /// Score of a matching document; unspecified for documents that do not match.
+    fn score(&self, segment: &Segment, doc_id: i64) -> f32;
+}
+
+/// Distinct terms of a stored document accepted by `accept`, re-tokenized with
+/// the tokenizer of the segment.
+fn matching_terms(segment: &Segment, doc_id: i64, accept: impl Fn(&str) -> bool) -> BTreeSet<String> {
+    match segment.document(doc_id) {
+        Some(document) => document_tokens(segment, &document).into_iter()
+            .map(|(token, _)| token)
+            .filter(|token| accept(token))
+            .collect(),
+        None => BTreeSet::new(),
+    }
+}
+
+/// Documents containing one exact term.
+#[derive(Clone, Debug)]
+pub struct TermQuery {
+    pub term: String,
+}
+
+impl TermQuery {
+    pub fn new(term: &str) -> Self {
+        TermQuery { term: term.to_string() }
+    }
+}
+
+impl Query for TermQuery {
+    fn matches(&self, segment: &Segment, doc_id: i64) -> bool {
+        segment.dict.get(&self.term).is_some_and(|posting| posting.contains(doc_id))
+    }
+
+    fn score(&self, segment: &Segment, doc_id: i64) -> f32 {
+        doc_term_score(segment, &self.term, doc_id).unwrap_or(0.0)
+    }
+}
+
+/// Documents holding the terms in order, each at most `slop` tokens after the
+/// previous one; a slop of 0 asks for adjacent terms. Postings carry no
+/// positions, so the stored document is tokenized again to check them.
+#[derive(Clone, Debug)]
+pub struct PhraseQuery {
+    pub terms: Vec<String>,
+    pub slop: u32,
+}
+
+impl PhraseQuery {
+    /// Exact phrase, split with `NaiveTokenizer`; the terms go through the
+    /// segment's tokenizer when matched, like the terms of a `search`.
+    pub fn new(phrase: &str) -> Self {
+        PhraseQuery::with_slop(phrase, 0)
+    }
+
+    pub fn with_slop(phrase: &str, slop: u32) -> Self {
+        let terms = NaiveTokenizer::new().tokenize_with_positions(phrase).into_iter().map(|(t, _)| t).collect();
+        PhraseQuery { terms, slop }
+    }
+
+    fn segment_terms(&self, segment: &Segment) -> Vec<String> {
+        query_terms(&self.terms.join(" "), segment.tokenizer.as_deref()).unwrap_or_default()
+    }
+
+    /// Whether `terms` occur in order after position `from`, each within the slop
+    /// of the one before. Backtracks, since the earliest occurrence of a term
+    /// is not always the one the rest of the phrase can follow.
+    fn follows(&self, terms: &[String], positions: &HashMap<&str, Vec<u32>>, from: u32) -> bool {
+        let (term, rest) = match terms.split_first() {
+            Some(split) => split,
+            None => return true,
+        };
+        positions.get(term.as_str()).is_some_and(|found| {
+            found.iter()
+                .filter(|&&pos| pos > from && pos <= from + self.slop + 1)
+                .any(|&pos| self.follows(rest, positions, pos))
+        })
+    }
+}
+
+impl Query for PhraseQuery {
+    fn matches(&self, segment: &Segment, doc_id: i64) -> bool {
+        let terms = self.segment_terms(segment);
+        let document = match segment.document(doc_id) {
+            Some(document) if !terms.is_empty() => document,
+            _ => return false,
+        };
+        let tokens = document_tokens(segment, &document);
+        let mut positions: HashMap<&str, Vec<u32>> = HashMap::new();
+        for (token, pos) in &tokens {
+            if terms.contains(token) {
+                positions.entry(token.as_str()).or_default().push(*pos);
+            }
+        }
+        let (first, rest) = terms.split_first().expect("checked non-empty");
+        positions.get(first.as_str())
+            .is_some_and(|found| found.iter().any(|&pos| self.follows(rest, &positions, pos)))
+    }
+
+    fn score(&self, segment: &Segment, doc_id: i64) -> f32 {
+        let terms: BTreeSet<String> = self.segment_terms(segment).into_iter().collect();
+        terms.iter().filter_map(|term| doc_term_score(segment, term, doc_id)).sum()
+    }
+}
+
+/// Documents with a term within `max_edits` Levenshtein edits of `term`. The
+/// scores of every such term are summed, as in `search_fuzzy`.
+#[derive(Clone, Debug)]
+pub struct FuzzyQuery {
+    pub term: String,
+    pub max_edits: usize,
+}
+
+impl FuzzyQuery {
+    pub fn new(term: &str, max_edits: usize) -> Self {
+        FuzzyQuery { term: term.to_string(), max_edits }
+    }
+
+    fn terms(&self, segment: &Segment, doc_id: i64) -> BTreeSet<String> {
+        matching_terms(segment, doc_id, |token| edit_distance(&self.term, token) <= self.max_edits)
+    }
+}
+
+impl Query for FuzzyQuery {
+    fn matches(&self, segment: &Segment, doc_id: i64) -> bool {
+        !self.terms(segment, doc_id).is_empty()
+    }
+
+    fn score(&self, segment: &Segment, doc_id: i64) -> f32 {
+        self.terms(segment, doc_id).iter().filter_map(|term| doc_term_score(segment, term, doc_id)).sum()
+    }
+}
+
+/// Documents with a term matching `pattern`, where `*` stands for any run of
+/// characters and `?` for exactly one.
+#[derive(Clone, Debug)]
+pub struct WildcardQuery {
+    pub pattern: String,
+}
+
+impl WildcardQuery {
+    pub fn new(pattern: &str) -> Self {
+        WildcardQuery { pattern: pattern.to_string() }
+    }
+
+    fn terms(&self, segment: &Segment, doc_id: i64) -> BTreeSet<String> {
+        let pattern: Vec<char> = self.pattern.chars().collect();
+        matching_terms(segment, doc_id, |token| wildcard_match(&pattern, &token.chars().collect::<Vec<char>>()))
+    }
+}
+
+impl Query for WildcardQuery {
+    fn matches(&self, segment: &Segment, doc_id: i64) -> bool {
+        !self.terms(segment, doc_id).is_empty()
+    }
+
+    fn score(&self, segment: &Segment, doc_id: i64) -> f32 {
+        self.terms(segment, doc_id).iter().filter_map(|term| doc_term_score(segment, term, doc_id)).sum()
+    }
+}
+
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    // matched[j]: the pattern read so far matches text[..j]
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    for &p in pattern {
+        let mut next = vec![false; text.len() + 1];
+        match p {
+            '*' => {
+                let mut any = false;
+                for j in 0..=text.len() {
+                    any |= matched[j];
+                    next[j] = any;
+                }
+            }
+            _ => {
+                for j in 0..text.len() {
+                    next[j + 1] = matched[j] && (p == '?' || p == text[j]);
+                }
+            }
+        }
+        matched = next;
+    }
+    matched[text.len()]
+}
+
+/// Runs a `PhraseQuery` allowing up to `slop` extra tokens between consecutive
+/// phrase terms.
+pub fn search_phrase(segment: &Segment, phrase: &str, slop: u32, size: i32) -> Vec<TopKDoc> {
+    search_query(segment, &PhraseQuery::with_slop(phrase, slop), size)
+}
+
+/// Evaluates `query` against every stored document, best matches first; equal
+/// scores are ordered by doc_id. Costs a full scan of the segment, so prefer
+/// `search` when a plain term query will do. An unbuilt segment has no results.
+pub fn search_query(segment: &Segment, query: &dyn Query, size: i32) -> Vec<TopKDoc> {
+    if !segment.is_built() {
+        return Vec::new();
+    }
+    let mut top_k: BinaryHeap<TopKDoc> = segment.docs.keys()
+        .filter(|&&doc_id| query.matches(segment, doc_id))
+        .map(|&id| TopKDoc { id, score: F32(query.score(segment, id)) })
+        .collect();
+    let mut result = Vec::new();
+    while let Some(doc) = top_k.pop() {
+        if result.len() == size.max(0) as usize {
+            break;
+        }
+        result.push(doc);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BooleanQuery, Document, FuzzyQuery, index_documents, PhraseQuery, Query, search, search_phrase,
+                search_query, Segment, TermQuery, WildcardQuery};
+
+    fn segment() -> Segment {
+        index_documents(vec![
+            Document::new(1, "rust search engine"),
+            Document::new(2, "search for rust"),
+            Document::new(3, "a rusty engine"),
+            Document::new(4, "searching engines"),
+        ]).expect("")
+    }
+
+    fn ids(segment: &Segment, query: &dyn Query) -> Vec<i64> {
+        let mut ids: Vec<i64> = search_query(segment, query, 10).iter().map(|d| d.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn term_query_success() {
+        let segment = segment();
+        assert_eq!(ids(&segment, &TermQuery::new("rust")), vec![1, 2]);
+        assert!(ids(&segment, &TermQuery::new("missing")).is_empty());
+        let expected: Vec<(i64, f32)> = search(&segment, "engine".to_string(), 10).expect("")
+            .iter().map(|d| (d.id, d.score.0)).collect();
+        let found: Vec<(i64, f32)> = search_query(&segment, &TermQuery::new("engine"), 10)
+            .iter().map(|d| (d.id, d.score.0)).collect();
+        assert_eq!(found, expected);
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn boolean_query_success() {
+        let segment = segment();
+        let query = BooleanQuery {
+            must: vec!["engine".to_string()],
+            should: vec!["search".to_string()],
+            must_not: vec!["rusty".to_string()],
+            ..BooleanQuery::new()
+        };
+        assert_eq!(ids(&segment, &query), vec![1]);
+        let query = BooleanQuery { should: vec!["rust".to_string(), "rusty".to_string()], ..BooleanQuery::new() };
+        assert_eq!(ids(&segment, &query), vec![1, 2, 3]);
+        assert!(ids(&segment, &BooleanQuery::new()).is_empty());
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn phrase_query_success() {
+        let segment = segment();
+        assert_eq!(ids(&segment, &PhraseQuery::new("search engine")), vec![1]);
+        assert_eq!(ids(&segment, &PhraseQuery::new("rust")), vec![1, 2]);
+        assert!(ids(&segment, &PhraseQuery::new("engine search")).is_empty());
+        assert!(ids(&segment, &PhraseQuery::new("")).is_empty());
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn sloppy_phrase_success() {
+        let segment = index_documents(vec![
+            Document::new(1, "the quick brown fox"),
+            Document::new(2, "the quick very very very long gap fox"),
+            Document::new(3, "the quick fox"),
+            Document::new(4, "fox quick"),
+            Document::new(5, "quick quick a b fox"),
+        ]).expect("");
+        let ids = |phrase: &str, slop: u32| -> Vec<i64> {
+            let mut ids: Vec<i64> = search_phrase(&segment, phrase, slop, 10).iter().map(|d| d.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids("quick fox", 0), vec![3]);
+        assert_eq!(ids("quick fox", 1), vec![1, 3]);
+        assert_eq!(ids("quick fox", 2), vec![1, 3, 5]);
+        assert_eq!(ids("quick fox", 6), vec![1, 2, 3, 5]);
+        assert_eq!(ids("the quick very fox", 4), vec![2]);
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn fuzzy_query_success() {
+        let segment = segment();
+        assert_eq!(ids(&segment, &FuzzyQuery::new("rust", 0)), vec![1, 2]);
+        assert_eq!(ids(&segment, &FuzzyQuery::new("rust", 1)), vec![1, 2, 3]);
+        assert_eq!(ids(&segment, &FuzzyQuery::new("engin", 1)), vec![1, 3]);
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn wildcard_query_success() {
+        let segment = segment();
+        assert_eq!(ids(&segment, &WildcardQuery::new("search*")), vec![1, 2, 4]);
+        assert_eq!(ids(&segment, &WildcardQuery::new("rus?")), vec![1, 2]);
+        assert_eq!(ids(&segment, &WildcardQuery::new("*ine?")), vec![4]);
+        assert_eq!(ids(&segment, &WildcardQuery::new("*")), vec![1, 2, 3, 4]);
+        assert!(ids(&segment, &WildcardQuery::new("?")).contains(&3));
+        crate::assert_sorted(&segment);
+    }
+}
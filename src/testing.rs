@@ -0,0 +1,52 @@
+//! White-box helpers for tests outside the crate, behind the `testing` feature.
+//! They bypass the invariants the public API maintains, so the feature must
+//! never be enabled in production builds.
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use crate::{document_tokens, Document, Iter, PostingList, Segment};
+
+/// Builds a searchable segment from pre-built posting lists, as if they had come
+/// out of indexing `documents`. The lists are taken as given: nothing checks
+/// that they agree with the documents or are well-formed.
+pub fn segment_with_postings(postings: impl IntoIterator<Item = (String, PostingList)>,
+                             documents: Vec<Document>) -> Segment {
+    let mut segment = Segment::new();
+    for (term, posting) in postings {
+        segment.dict.insert(&term, posting);
+    }
+    for document in documents {
+        let tokens = document_tokens(&segment, &document).len();
+        segment.doc_lengths.insert(document.id, tokens as u32);
+        segment.total_tokens_indexed += tokens as u64;
+        segment.docs.insert(document.id, Arc::new(document));
+    }
+    segment.total_docs = segment.docs.len();
+    segment.built.store(true, Ordering::Release);
+    segment
+}
+
+/// Terms `document` is indexed under in `segment`, with their positions.
+pub fn tokens_of(segment: &Segment, document: &Document) -> Vec<(String, u32)> {
+    document_tokens(segment, document)
+}
+
+/// The `(doc_id, freq)` pairs the scoring loop visits for `posting`, in order,
+/// together with the document frequency its cursor caches.
+pub fn cursor_walk(posting: &PostingList) -> (usize, Vec<(i64, i32)>) {
+    let mut iterator = match Iter::new(posting) {
+        Some(iterator) => iterator,
+        None => return (0, Vec::new()),
+    };
+    let mut visited = vec![(iterator.doc_id, iterator.freq)];
+    while iterator.advance() {
+        visited.push((iterator.doc_id, iterator.freq));
+    }
+    (iterator.doc_freq, visited)
+}
+
+/// Panics unless every posting list of `segment` is strictly sorted by doc_id.
+pub fn assert_sorted(segment: &Segment) {
+    crate::assert_sorted(segment)
+}
@@ -0,0 +1,160 @@
+//! Trigram index over the terms of a segment, used to narrow fuzzy lookups to
+//! the terms that share at least one character trigram with the query.
+
+use std::collections::{HashMap, HashSet};
+
+/// Maps every trigram to the terms containing it. Terms are padded with two
+/// boundary markers on each side, so a term of n characters has n + 2 trigrams
+/// and even one-letter terms are indexed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrigramIndex {
+    pub index: HashMap<String, HashSet<String>>,
+}
+
+impl TrigramIndex {
+    pub fn new() -> Self {
+        TrigramIndex::default()
+    }
+
+    pub fn from_terms<'a>(terms: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut index = TrigramIndex::new();
+        for term in terms {
+            index.insert(term);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, term: &str) {
+        for trigram in trigrams(term) {
+            self.index.entry(trigram).or_default().insert(term.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, term: &str) {
+        for trigram in trigrams(term) {
+            if let Some(terms) = self.index.get_mut(&trigram) {
+                terms.remove(term);
+                if terms.is_empty() {
+                    self.index.remove(&trigram);
+                }
+            }
+        }
+    }
+}
+
+const BOUNDARY: char = '\u{0}';
+
+fn trigrams(term: &str) -> Vec<String> {
+    let padded: Vec<char> = [BOUNDARY, BOUNDARY].into_iter()
+        .chain(term.chars())
+        .chain([BOUNDARY, BOUNDARY])
+        .collect();
+    padded.windows(3).map(|window| window.iter().collect()).collect()
+}
+
+/// Terms sharing at least one trigram with `query`, most shared trigrams first
+/// and then in lexicographic order.
+///
+/// One edit changes at most three of a term's trigrams, so every term within
+/// one edit of a query of two or more characters is among the candidates. The
+/// candidates are not verified: callers still have to compute the distance.
+pub fn find_candidates(tri_idx: &TrigramIndex, query: &str) -> Vec<String> {
+    let mut shared: HashMap<&str, usize> = HashMap::new();
+    let mut seen = HashSet::new();
+    for trigram in trigrams(query) {
+        if !seen.insert(trigram.clone()) {
+            continue;
+        }
+        for term in tri_idx.index.get(&trigram).into_iter().flatten() {
+            *shared.entry(term.as_str()).or_insert(0) += 1;
+        }
+    }
+    let mut candidates: Vec<(&str, usize)> = shared.into_iter().collect();
+    candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    candidates.into_iter().map(|(term, _)| term.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{apply_tx, Document, find_candidates, index_documents_with_config, IndexConfig, PostingListTx, search,
+                search_fuzzy, TrigramIndex};
+
+    #[test]
+    fn find_candidates_success() {
+        let terms = ["search", "rust", "engine", "index", "go", "segment", "posting"];
+        let tri_idx = TrigramIndex::from_terms(terms);
+        for term in terms {
+            let chars: Vec<char> = term.chars().collect();
+            let mut misspellings = Vec::new();
+            for i in 0..chars.len() {
+                let mut deleted = chars.clone();
+                deleted.remove(i);
+                misspellings.push(deleted);
+                let mut substituted = chars.clone();
+                substituted[i] = 'x';
+                misspellings.push(substituted);
+            }
+            for i in 0..=chars.len() {
+                let mut inserted = chars.clone();
+                inserted.insert(i, 'x');
+                misspellings.push(inserted);
+            }
+            for misspelling in misspellings {
+                let query: String = misspelling.into_iter().collect();
+                let candidates = find_candidates(&tri_idx, &query);
+                assert!(candidates.iter().any(|c| c == term), "{} not a candidate for {}", term, query);
+            }
+        }
+
+        assert_eq!(find_candidates(&tri_idx, "search")[0], "search");
+        assert!(find_candidates(&tri_idx, "qqqq").is_empty());
+    }
+
+    #[test]
+    fn search_fuzzy_trigram_index_success() {
+        let config = IndexConfig { build_trigram_index: true, ..IndexConfig::default() };
+        let segment = index_documents_with_config(vec![
+            Document::new(1, "rust search"),
+            Document::new(2, "trust engine"),
+            Document::new(3, "seerch"),
+        ], &config).expect("");
+        assert!(segment.trigrams.as_ref().expect("").index.values().flatten().any(|t| t == "engine"));
+
+        let plain = index_documents_with_config(vec![
+            Document::new(1, "rust search"),
+            Document::new(2, "trust engine"),
+            Document::new(3, "seerch"),
+        ], &IndexConfig::default()).expect("");
+        assert!(plain.trigrams.is_none());
+        for query in ["rust", "serch", "engin"] {
+            let mut with_index: Vec<i64> = search_fuzzy(&segment, query, 1, 0.0, 10).expect("").iter().map(|d| d.id).collect();
+            let mut without: Vec<i64> = search_fuzzy(&plain, query, 1, 0.0, 10).expect("").iter().map(|d| d.id).collect();
+            with_index.sort();
+            without.sort();
+            assert_eq!(with_index, without, "{}", query);
+        }
+        crate::assert_sorted(&segment);
+    }
+
+    #[test]
+    fn apply_tx_trigram_index_success() {
+        let config = IndexConfig { build_trigram_index: true, ..IndexConfig::default() };
+        let mut segment = index_documents_with_config(vec![Document::new(1, "rust"), Document::new(2, "go")], &config)
+            .expect("");
+        let tx = PostingListTx { adds: vec![("engine".to_string(), 2, 1)], removes: Vec::new() };
+        apply_tx(&mut segment, tx).expect("");
+        assert_eq!(search(&segment, "engine".to_string(), 10).expect("")[0].id, 2);
+        for query in ["engine", "engin", "enxine"] {
+            let ids: Vec<i64> = search_fuzzy(&segment, query, 1, 0.0, 10).expect("").iter().map(|d| d.id).collect();
+            assert_eq!(ids, vec![2], "{}", query);
+        }
+
+        // a rolled back transaction leaves no trigrams for the terms it added
+        let tx = PostingListTx { adds: vec![("zebra".to_string(), 1, 1), ("zebra".to_string(), 42, 1)], removes: Vec::new() };
+        assert!(apply_tx(&mut segment, tx).is_err());
+        let trigrams = segment.trigrams.as_ref().expect("");
+        assert!(!trigrams.index.values().flatten().any(|t| t == "zebra"));
+        assert!(find_candidates(trigrams, "zebra").is_empty());
+        crate::assert_sorted(&segment);
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use crate::multi_segment::search_segments;
+use crate::{Document, index_documents, SearchConfig, SearchError, Segment, TopKDoc};
+
+/// Buffers documents and turns them into a new segment on every `commit`.
+/// Each commit bumps the writer's generation.
+pub struct IndexWriter {
+    pending: Vec<Document>,
+    segments: Vec<Arc<Segment>>,
+    generation: u64,
+}
+
+/// Point-in-time view of the segments committed by an `IndexWriter`.
+pub struct IndexReader {
+    segments: Vec<Arc<Segment>>,
+    generation: u64,
+}
+
+impl IndexWriter {
+    pub fn new() -> Self {
+        IndexWriter { pending: Vec::new(), segments: Vec::new(), generation: 0 }
+    }
+
+    pub fn add_document(&mut self, document: Document) {
+        self.pending.push(document);
+    }
+
+    /// Indexes the pending documents into a segment and returns a reader that sees it.
+    pub fn commit(&mut self) -> Result<IndexReader, SearchError> {
+        if !self.pending.is_empty() {
+            let documents = std::mem::take(&mut self.pending);
+            self.segments.push(Arc::new(index_documents(documents)?));
+        }
+        self.generation += 1;
+        Ok(self.reader())
+    }
+
+    /// A reader over everything committed so far.
+    pub fn reader(&self) -> IndexReader {
+        IndexReader { segments: self.segments.clone(), generation: self.generation }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl Default for IndexWriter {
+    fn default() -> Self {
+        IndexWriter::new()
+    }
+}
+
+impl IndexReader {
+    pub fn search(&self, query: &str, size: i32) -> Result<Vec<TopKDoc>, SearchError> {
+        self.search_with_config(query, size, &SearchConfig::default())
+    }
+
+    pub fn search_with_config(&self, query: &str, size: i32,
+                              config: &SearchConfig) -> Result<Vec<TopKDoc>, SearchError> {
+        search_segments(self.segments.iter().map(|s| s.as_ref()), query, size, config)
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// True when no commit happened on `writer` since this reader was taken.
+    pub fn is_fresh(&self, writer: &IndexWriter) -> bool {
+        self.generation == writer.generation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Document, IndexReader, IndexWriter};
+
+    #[test]
+    fn read_your_own_writes_success() {
+        let mut writer = IndexWriter::new();
+        writer.add_document(Document::new(1, "hello world"));
+        writer.add_document(Document::new(2, "filler"));
+        let reader = writer.commit().expect("");
+        assert!(reader.is_fresh(&writer));
+        assert_eq!(reader.search("hello", 10).expect("")[0].id, 1);
+
+        writer.add_document(Document::new(3, "hello again"));
+        writer.add_document(Document::new(4, "filler"));
+        assert!(reader.is_fresh(&writer));
+        let fresh = writer.commit().expect("");
+        assert!(!reader.is_fresh(&writer));
+        assert!(fresh.is_fresh(&writer));
+        assert_eq!(fresh.generation(), 2);
+
+        assert_eq!(reader.search("again", 10).expect("").len(), 0);
+        let ids: Vec<i64> = fresh.search("again", 10).expect("").iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![3]);
+    }
+
+    #[test]
+    fn reader_on_another_thread_success() {
+        fn assert_send<T: Send>() {}
+        assert_send::<IndexWriter>();
+        assert_send::<IndexReader>();
+
+        let mut writer = IndexWriter::new();
+        writer.add_document(Document::new(1, "hello world"));
+        let reader = writer.commit().expect("");
+        let found = std::thread::spawn(move || reader.search("hello", 10).expect("")).join().expect("");
+        assert_eq!(found[0].id, 1);
+    }
+}
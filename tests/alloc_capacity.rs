@@ -0,0 +1,53 @@
+//! Counts heap allocations to check that the capacity hint of
+//! `index_documents_with_capacity` saves the map regrowth.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use danton::{Document, index_documents_with_capacity, index_documents_with_config, IndexConfig};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations_during<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.with(|count| count.get());
+    let result = f();
+    let after = ALLOCATIONS.with(|count| count.get());
+    drop(result);
+    after - before
+}
+
+#[test]
+fn index_with_capacity_allocates_less() {
+    let documents: Vec<Document> = (0..2_000).map(|id| Document::new(id, &format!("word{}", id))).collect();
+
+    let without_hint = allocations_during(|| {
+        index_documents_with_config(documents.clone(), &IndexConfig::default()).expect("")
+    });
+    let with_hint = allocations_during(|| {
+        index_documents_with_capacity(documents.clone(), documents.len(), IndexConfig::default()).expect("")
+    });
+    assert!(with_hint < without_hint, "{} allocations with the hint, {} without", with_hint, without_hint);
+}
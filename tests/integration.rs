@@ -0,0 +1,46 @@
+use std::fs;
+
+use tempfile::TempDir;
+
+use danton::{flush_to_disk, index_directory, load_from_disk, search, TopKDoc};
+
+const FILES: [(&str, &str); 5] = [
+    ("a.txt", "the quick brown fox"),
+    ("b.txt", "the lazy dog sleeps"),
+    ("c.txt", "quick quick dog"),
+    ("d.txt", "a brown dog and a brown fox"),
+    ("e.txt", "nothing in common"),
+];
+
+const QUERIES: [&str; 5] = ["quick", "brown fox", "dog", "the", "missing"];
+
+fn ids_and_scores(results: &[TopKDoc]) -> Vec<(i64, f32)> {
+    results.iter().map(|r| (r.id, r.score.0)).collect()
+}
+
+#[test]
+fn index_flush_reload_search_success() {
+    let texts = TempDir::new().expect("");
+    for (name, text) in FILES {
+        fs::write(texts.path().join(name), text).expect("");
+    }
+    let index = TempDir::new().expect("");
+    let index_path = index.path().to_str().expect("");
+
+    let segment = index_directory(texts.path().to_str().expect("")).expect("");
+    assert_eq!(segment.total_docs, FILES.len());
+    let before: Vec<Vec<(i64, f32)>> = QUERIES.iter()
+        .map(|q| ids_and_scores(&search(&segment, q.to_string(), 10).expect("")))
+        .collect();
+    flush_to_disk(&segment, index_path).expect("");
+    drop(segment);
+
+    let loaded = load_from_disk(index_path).expect("");
+    assert_eq!(loaded.total_docs, FILES.len());
+    for (query, expected) in QUERIES.iter().zip(&before) {
+        let found = ids_and_scores(&search(&loaded, query.to_string(), 10).expect(""));
+        assert_eq!(&found, expected, "query {:?}", query);
+    }
+    assert_eq!(before[0].len(), 2);
+    assert!(before[4].is_empty());
+}
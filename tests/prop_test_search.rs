@@ -0,0 +1,44 @@
+use std::collections::HashSet;
+
+use proptest::prelude::*;
+
+use danton::{Document, index_documents, search};
+
+const WORDS: [&str; 8] = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta"];
+
+fn word() -> impl Strategy<Value = String> {
+    prop::sample::select(&WORDS[..]).prop_map(|w| w.to_string())
+}
+
+fn corpus() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec(prop::collection::vec(word(), 1..12).prop_map(|w| w.join(" ")), 1..40)
+}
+
+fn query() -> impl Strategy<Value = String> {
+    prop::collection::vec(word(), 1..4).prop_map(|w| w.join(" "))
+}
+
+proptest! {
+    #[test]
+    fn prop_search_invariants(texts in corpus(), query in query(), size in 1i32..20) {
+        let documents: Vec<Document> = texts.iter().enumerate()
+            .map(|(id, text)| Document::new(id as i64, text))
+            .collect();
+        let segment = index_documents(documents).expect("");
+        let results = search(&segment, query.clone(), size).expect("");
+        let query_tokens: HashSet<&str> = query.split_whitespace().collect();
+
+        let mut seen = HashSet::new();
+        for result in &results {
+            let text = &texts[result.id as usize];
+            prop_assert!(text.split_whitespace().any(|t| query_tokens.contains(t)),
+                         "doc {} ({:?}) matches none of {:?}", result.id, text, query);
+            prop_assert!(result.score.0 >= 0.0, "negative score {} for doc {}", result.score.0, result.id);
+            prop_assert!(seen.insert(result.id), "doc {} returned twice", result.id);
+        }
+        for pair in results.windows(2) {
+            prop_assert!(pair[0].score.0 >= pair[1].score.0,
+                         "scores out of order: {} before {}", pair[0].score.0, pair[1].score.0);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+#![cfg(feature = "testing")]
+
+use danton::testing::{assert_sorted, cursor_walk, segment_with_postings, tokens_of};
+use danton::{Document, PostingList, PostingNode, search};
+
+fn posting(nodes: &[(i64, i32)]) -> PostingList {
+    nodes.iter().map(|&(doc_id, freq)| PostingNode { doc_id, freq }).collect()
+}
+
+#[test]
+fn injected_postings_are_searched() {
+    let documents = vec![Document::new(1, "rust rust"), Document::new(2, "rust")];
+    let segment = segment_with_postings(vec![("rust".to_string(), posting(&[(1, 2), (2, 1)]))], documents);
+
+    let ids: Vec<i64> = search(&segment, "rust".to_string(), 10).expect("").iter().map(|d| d.id).collect();
+    assert_eq!(ids, vec![1, 2]);
+    assert_eq!(tokens_of(&segment, &segment.docs[&1]), vec![("rust".to_string(), 0), ("rust".to_string(), 1)]);
+
+    let (doc_freq, visited) = cursor_walk(&segment.dict.get("rust").expect(""));
+    assert_eq!(doc_freq, 2);
+    assert_eq!(visited, vec![(1, 2), (2, 1)]);
+    assert_sorted(&segment);
+}